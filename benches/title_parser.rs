@@ -0,0 +1,20 @@
+use ottobangumi::title_corpus;
+use std::time::Duration;
+
+/// Parsing speed of the title parser on the corpus in the repository.
+///
+/// cargo bench --bench title_parser
+fn main() {
+    let text = std::fs::read_to_string(title_corpus::CORPUS_PATH).unwrap();
+    let cases = title_corpus::load(&text).unwrap();
+    // warm up the compiled patterns
+    title_corpus::bench(&cases, Duration::from_millis(200));
+    let (titles, elapsed) = title_corpus::bench(&cases, Duration::from_secs(3));
+    println!(
+        "title_parser: {} titles in {:.2?}, {:.0} titles/s, {:.1?} per title",
+        titles,
+        elapsed,
+        titles as f64 / elapsed.as_secs_f64(),
+        elapsed / titles.max(1) as u32
+    );
+}
@@ -0,0 +1,59 @@
+use ottobangumi::title_corpus;
+use std::env;
+use std::time::Duration;
+
+const BENCH_TIME: Duration = Duration::from_secs(3);
+
+/// Report how accurately the title parser handles a corpus, by default the
+/// one in the repository. With `-v` every wrong field is listed as well,
+/// with `--bench` the parsing speed is measured instead.
+///
+/// cargo run --release --bin title_report -- [-v|--bench] [corpus.tsv]
+fn main() {
+    let mut verbose = false;
+    let mut bench = false;
+    let mut path = title_corpus::CORPUS_PATH.to_string();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "-v" | "--verbose" => verbose = true,
+            "--bench" => bench = true,
+            _ => path = arg,
+        }
+    }
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("cannot read {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    let cases = match title_corpus::load(&text) {
+        Ok(cases) => cases,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if bench {
+        let (titles, elapsed) = title_corpus::bench(&cases, BENCH_TIME);
+        println!(
+            "{} titles in {:.2?}, {:.0} titles/s, {:.1?} per title",
+            titles,
+            elapsed,
+            titles as f64 / elapsed.as_secs_f64(),
+            elapsed / titles.max(1) as u32
+        );
+        return;
+    }
+    let accuracy = title_corpus::evaluate(&cases);
+    if verbose {
+        for m in accuracy.mismatches.iter() {
+            println!(
+                "{}\n  {}: expected {:?}, got {:?}",
+                m.title, m.field, m.expected, m.actual
+            );
+        }
+        println!();
+    }
+    println!("{}", accuracy);
+}
@@ -1,8 +1,11 @@
-use crate::{database, mikan, utils};
+use crate::i18n::{fill, Locale, Msg};
+use crate::{
+    database, downloader, error, library, mikan, organizer, seeding, title_parser, updater, utils,
+};
 use anyhow::Result;
 use std::sync::Arc;
 use teloxide::dispatching::DefaultKey;
-use teloxide::types::InputFile;
+use teloxide::types::{BotCommand, InlineKeyboardButton, InlineKeyboardMarkup, InputFile};
 use teloxide::{prelude::*, utils::command::BotCommands};
 
 #[derive(BotCommands, Clone, Debug)]
@@ -17,9 +20,11 @@ pub enum Command {
     List,
     #[command(description = "get bangumi info.\nUsage: /info <id>")]
     Info(u32),
-    #[command(description = "add a bangumi.\nUsage: /add <rss_url>")]
+    #[command(description = "add a bangumi.\nUsage: /add <rss_url> [all|new|<from_episode>]")]
     Add(String),
-    #[command(description = "remove a bangumi.\nUsage: /remove <id>")]
+    #[command(
+        description = "remove a bangumi, choosing what happens to its files.\nUsage: /remove <id>"
+    )]
     Remove(u32),
     #[command(description = "enable rss.\nUsage: /enable <id>")]
     Enable(u32),
@@ -31,6 +36,226 @@ pub enum Command {
         rename = "nc"
     )]
     NotContains(u32, String),
+    #[command(
+        description = "download past episodes still in the feed.\nUsage: /backfill <id> <from>-<to>",
+        parse_with = "split"
+    )]
+    Backfill(u32, String),
+    #[command(description = "download a single episode.\nUsage: /get <episode_url>")]
+    Get(String),
+    #[command(description = "scan library against database.\nUsage: /scan [requeue]")]
+    Scan(String),
+    #[command(description = "notify this chat about a bangumi.\nUsage: /follow <id>")]
+    Follow(u32),
+    #[command(description = "stop notifying this chat about a bangumi.\nUsage: /unfollow <id>")]
+    Unfollow(u32),
+    #[command(description = "set notifications of this chat.\nUsage: /notify <all|followed|off>")]
+    Notify(String),
+    #[command(
+        description = "post releases to a channel.\nUsage: /channel <id> <@channel|chat_id|none>",
+        parse_with = "split"
+    )]
+    Channel(u32, String),
+    #[command(description = "list users.")]
+    Users,
+    #[command(
+        description = "set user role.\nUsage: /user <user_id> <admin|member|viewer|remove>",
+        parse_with = "split"
+    )]
+    User(u64, String),
+    #[command(description = "set language.\nUsage: /lang <en|zh>")]
+    Lang(String),
+    #[command(description = "check one or all subscriptions now.\nUsage: /check [id]")]
+    Check(String),
+    #[command(
+        description = "set how often a feed is polled.\nUsage: /poll <id> <auto|minutes>",
+        parse_with = "split"
+    )]
+    Poll(u32, String),
+    #[command(
+        description = "list failed downloads, or retry those of a bangumi now.\nUsage: /retry [id]"
+    )]
+    Retry(String),
+    #[command(
+        description = "list unrecognized titles, or give their episode.\nUsage: /resolve [<item> <season> <episode>|<item> ignore]"
+    )]
+    Resolve(String),
+    #[command(
+        description = "list or edit title parsing rules.\nUsage: /parserule [add <fansub|id> <regex>|remove <rule_id>]"
+    )]
+    ParseRule(String),
+    #[command(description = "show how a title is parsed.\nUsage: /testparse [id] <title>")]
+    TestParse(String),
+    #[command(
+        description = "show or set how torrents are seeded.\nUsage: /seed [id] [ratio=<ratio>] [time=<minutes>] [upload=<KiB/s>] [remove=on|off]|default"
+    )]
+    Seed(String),
+}
+
+impl Command {
+    pub fn required_role(&self) -> database::Role {
+        match self {
+            Command::Help
+            | Command::List
+            | Command::Info(_)
+            | Command::Follow(_)
+            | Command::Unfollow(_)
+            | Command::Notify(_)
+            | Command::Lang(_)
+            | Command::TestParse(_) => database::Role::Viewer,
+            Command::Scan(_) | Command::Users | Command::User(..) => database::Role::Admin,
+            _ => database::Role::Member,
+        }
+    }
+    /// Commands changing settings of the whole chat, which in groups only
+    /// group admins and bot admins may use.
+    pub fn changes_chat(&self) -> bool {
+        matches!(
+            self,
+            Command::Follow(_) | Command::Unfollow(_) | Command::Notify(_) | Command::Lang(_)
+        )
+    }
+    /// The bangumi a command modifies, which members may only do for their own.
+    pub fn managed_bangumi(&self) -> Option<u32> {
+        match self {
+            Command::Remove(id)
+            | Command::Enable(id)
+            | Command::Disable(id)
+            | Command::NotContains(id, _)
+            | Command::Backfill(id, _)
+            | Command::Channel(id, _)
+            | Command::Poll(id, _) => Some(*id),
+            // without an id every enabled subscription is checked
+            Command::Check(id) => id.trim().parse().ok(),
+            _ => None,
+        }
+    }
+    /// Commands with descriptions in `locale`.
+    pub fn localized_commands(locale: Locale) -> Vec<BotCommand> {
+        Command::bot_commands()
+            .into_iter()
+            .map(
+                |c| match locale.command_description(c.command.trim_start_matches('/')) {
+                    Some(description) => BotCommand::new(c.command, description),
+                    None => c,
+                },
+            )
+            .collect()
+    }
+}
+
+/// Actions behind the inline keyboard buttons, encoded as `action:id[:arg]`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Callback {
+    List,
+    Show(u32),
+    Info(u32),
+    Enable(u32),
+    Disable(u32),
+    Filters(u32),
+    ClearFilters(u32),
+    Remove(u32),
+    ConfirmRemove(u32, organizer::RemoveMode),
+    Pause(u32),
+    SkipGroup(u32, String),
+    /// bangumi, quarantined item, season and episode
+    Resolve(u32, u32, i8, i16),
+    IgnoreItem(u32, u32),
+}
+
+impl Callback {
+    pub fn data(&self) -> String {
+        match self {
+            Callback::List => "list".to_string(),
+            Callback::Show(id) => format!("show:{}", id),
+            Callback::Info(id) => format!("info:{}", id),
+            Callback::Enable(id) => format!("enable:{}", id),
+            Callback::Disable(id) => format!("disable:{}", id),
+            Callback::Filters(id) => format!("filters:{}", id),
+            Callback::ClearFilters(id) => format!("clear_filters:{}", id),
+            Callback::Remove(id) => format!("remove:{}", id),
+            Callback::ConfirmRemove(id, mode) => {
+                format!("confirm_remove:{}:{}", id, mode.as_str())
+            }
+            Callback::Pause(id) => format!("pause:{}", id),
+            Callback::SkipGroup(id, group) => {
+                let mut data = format!("skip_group:{}:", id);
+                // callback data is limited to 64 bytes, a prefix of the group
+                // name still works as a not contains word
+                for ch in group.chars() {
+                    if data.len() + ch.len_utf8() > 64 {
+                        break;
+                    }
+                    data.push(ch);
+                }
+                data
+            }
+            Callback::Resolve(id, item, season, episode) => {
+                format!("resolve:{}:{}:{}:{}", id, item, season, episode)
+            }
+            Callback::IgnoreItem(id, item) => format!("ignore_item:{}:{}", id, item),
+        }
+    }
+    pub fn parse(data: &str) -> Option<Self> {
+        if data == "list" {
+            return Some(Callback::List);
+        }
+        let mut parts = data.splitn(3, ':');
+        let action = parts.next()?;
+        let id = parts.next()?.parse().ok()?;
+        if action == "skip_group" {
+            return parts
+                .next()
+                .filter(|g| !g.is_empty())
+                .map(|g| Callback::SkipGroup(id, g.to_string()));
+        }
+        if action == "resolve" {
+            let mut args = parts.next()?.split(':');
+            return Some(Callback::Resolve(
+                id,
+                args.next()?.parse().ok()?,
+                args.next()?.parse().ok()?,
+                args.next()?.parse().ok()?,
+            ));
+        }
+        if action == "confirm_remove" {
+            return Some(Callback::ConfirmRemove(id, parts.next()?.parse().ok()?));
+        }
+        if action == "ignore_item" {
+            return Some(Callback::IgnoreItem(id, parts.next()?.parse().ok()?));
+        }
+        match action {
+            "show" => Some(Callback::Show(id)),
+            "info" => Some(Callback::Info(id)),
+            "enable" => Some(Callback::Enable(id)),
+            "disable" => Some(Callback::Disable(id)),
+            "filters" => Some(Callback::Filters(id)),
+            "clear_filters" => Some(Callback::ClearFilters(id)),
+            "remove" => Some(Callback::Remove(id)),
+            "pause" => Some(Callback::Pause(id)),
+            _ => None,
+        }
+    }
+    pub fn id(&self) -> Option<u32> {
+        match self {
+            Callback::List => None,
+            Callback::Show(id)
+            | Callback::Info(id)
+            | Callback::Enable(id)
+            | Callback::Disable(id)
+            | Callback::Filters(id)
+            | Callback::ClearFilters(id)
+            | Callback::Remove(id)
+            | Callback::ConfirmRemove(id, _)
+            | Callback::Pause(id)
+            | Callback::SkipGroup(id, _)
+            | Callback::Resolve(id, ..)
+            | Callback::IgnoreItem(id, _) => Some(*id),
+        }
+    }
+    pub fn button(&self, text: &str) -> InlineKeyboardButton {
+        InlineKeyboardButton::callback(text, self.data())
+    }
 }
 
 pub struct Config {
@@ -39,6 +264,18 @@ pub struct Config {
     pub not_contains: Vec<String>,
     pub tmp_dir: String,
     pub lib_dir: String,
+    pub download_dir: String,
+    pub organize_mode: organizer::OrganizeMode,
+    /// for chats without a language of their own, e.g. channels
+    pub locale: Locale,
+    /// feeds updated at the same time
+    pub concurrency: usize,
+    /// failed downloads of an episode before giving up on it
+    pub retry_attempts: u32,
+    /// torrents outside this size in bytes are not downloaded
+    pub torrent_size: std::ops::RangeInclusive<u64>,
+    /// for bangumi without a seeding policy of their own
+    pub seeding: seeding::SeedingPolicy,
 }
 
 pub struct MyBot {
@@ -50,16 +287,33 @@ impl MyBot {
     pub async fn new(config: Arc<Config>, db: Arc<database::Client>) -> Result<Self> {
         let tg = Arc::new(Bot::from_env());
         tg.set_my_commands(Command::bot_commands()).await?;
+        for locale in Locale::ALL {
+            if locale != Locale::En {
+                tg.set_my_commands(Command::localized_commands(locale))
+                    .language_code(locale.language_code())
+                    .await?;
+            }
+        }
 
-        let handler = Update::filter_message().branch(
-            dptree::filter(|msg: Message, config: Arc<Config>| {
-                msg.from()
-                    .map(|user| config.user_ids.contains(&user.id.0))
-                    .unwrap_or_default()
-            })
-            .filter_command::<Command>()
-            .endpoint(bot_handler),
-        );
+        let handler = dptree::entry()
+            .branch(
+                Update::filter_message().branch(
+                    dptree::filter_map(|msg: Message, db: Arc<database::Client>| {
+                        resolve_user(&db, &msg.chat, msg.from())
+                    })
+                    .filter_command::<Command>()
+                    .endpoint(bot_handler),
+                ),
+            )
+            .branch(
+                Update::filter_callback_query().branch(
+                    dptree::filter_map(|q: CallbackQuery, db: Arc<database::Client>| {
+                        let chat = q.message.as_ref().map(|m| &m.chat)?;
+                        resolve_user(&db, chat, Some(&q.from))
+                    })
+                    .endpoint(callback_handler),
+                ),
+            );
 
         let dispatcher = Dispatcher::builder(tg.clone(), handler)
             .dependencies(dptree::deps![config.clone(), db.clone()])
@@ -92,16 +346,76 @@ impl MyBot {
     }
 }
 
+/// Registered users keep their role everywhere; anyone else in a group chat
+/// that has been set up with `/notify` may use read-only commands.
+fn resolve_user(
+    db: &database::Client,
+    chat: &teloxide::types::Chat,
+    from: Option<&teloxide::types::User>,
+) -> Option<database::User> {
+    let from = from?;
+    if let Some(user) = db.get_user(from.id.0).ok().flatten() {
+        return Some(user);
+    }
+    if chat.is_private() || db.get_chat(chat.id.0).ok().flatten().is_none() {
+        return None;
+    }
+    Some(database::User {
+        id: from.id.0,
+        role: database::Role::Viewer,
+        locale: None,
+    })
+}
+
+/// The language set for a group chat wins over the user's own, which falls
+/// back to the Telegram client's language.
+fn resolve_locale(
+    db: &database::Client,
+    config: &Config,
+    chat: &teloxide::types::Chat,
+    user: &database::User,
+    from: Option<&teloxide::types::User>,
+) -> Locale {
+    let chat_locale = if chat.is_private() {
+        None
+    } else {
+        db.get_chat(chat.id.0).ok().flatten().and_then(|c| c.locale)
+    };
+    chat_locale
+        .or(user.locale)
+        .or_else(|| Locale::from_language_code(from.and_then(|u| u.language_code.as_deref())))
+        .unwrap_or(config.locale)
+}
+
 pub async fn bot_handler(
     msg: Message,
     bot: Arc<Bot>,
     cmd: Command,
+    user: database::User,
     config: Arc<Config>,
     db: Arc<database::Client>,
 ) -> Result<()> {
     let chat_id = msg.chat.id;
     log::info!("ChatId: {:?}, Command: {:?}", chat_id, cmd);
-    let handler = BotHandler::new(bot.clone(), chat_id, config, db);
+    let locale = resolve_locale(&db, &config, &msg.chat, &user, msg.from());
+    let handler = BotHandler::new(bot.clone(), chat_id, user, locale, config, db);
+    let permitted = handler.user.role >= cmd.required_role()
+        && cmd
+            .managed_bangumi()
+            .map(|id| handler.can_manage(id))
+            .unwrap_or(true);
+    if !permitted {
+        bot.send_message(chat_id, handler.t(Msg::PermissionDenied))
+            .await?;
+        return Ok(());
+    }
+    let admin_only = cmd.required_role() > database::Role::Viewer
+        || (cmd.changes_chat() && handler.user.role < database::Role::Admin);
+    if admin_only && !handler.is_chat_admin().await {
+        bot.send_message(chat_id, handler.t(Msg::GroupAdminOnly))
+            .await?;
+        return Ok(());
+    }
     match cmd {
         Command::Help => handler.bot_help().await?,
         Command::List => handler.bangumi_list().await?,
@@ -111,13 +425,69 @@ pub async fn bot_handler(
         Command::Enable(id) => handler.bangumi_enable(id).await?,
         Command::Disable(id) => handler.bangumi_disable(id).await?,
         Command::NotContains(id, words) => handler.bangumi_not_contains(id, words).await?,
+        Command::Backfill(id, range) => handler.bangumi_backfill(id, range).await?,
+        Command::Get(url) => handler.episode_get(url).await?,
+        Command::Scan(arg) => handler.library_scan(arg).await?,
+        Command::Follow(id) => handler.bangumi_follow(id, true).await?,
+        Command::Unfollow(id) => handler.bangumi_follow(id, false).await?,
+        Command::Notify(mode) => handler.chat_notify(mode).await?,
+        Command::Channel(id, channel) => handler.bangumi_channel(id, channel).await?,
+        Command::Users => handler.user_list().await?,
+        Command::User(id, role) => handler.user_set(id, role).await?,
+        Command::Lang(locale) => handler.set_locale(locale).await?,
+        Command::Check(id) => handler.bangumi_check(id).await?,
+        Command::Poll(id, poll) => handler.bangumi_poll(id, poll).await?,
+        Command::Retry(id) => handler.bangumi_retry(id).await?,
+        Command::Resolve(args) => handler.quarantine_resolve(args).await?,
+        Command::ParseRule(args) => handler.parse_rule(args).await?,
+        Command::TestParse(args) => handler.test_parse(args).await?,
+        Command::Seed(args) => handler.bangumi_seed(args).await?,
+    };
+    Ok(())
+}
+
+pub async fn callback_handler(
+    q: CallbackQuery,
+    bot: Arc<Bot>,
+    user: database::User,
+    config: Arc<Config>,
+    db: Arc<database::Client>,
+) -> Result<()> {
+    let (msg, callback) = match (q.message, q.data.as_deref().and_then(Callback::parse)) {
+        (Some(msg), Some(callback)) => (msg, callback),
+        _ => {
+            bot.answer_callback_query(q.id).await?;
+            return Ok(());
+        }
     };
+    log::info!("ChatId: {:?}, Callback: {:?}", msg.chat.id, callback);
+    let locale = resolve_locale(&db, &config, &msg.chat, &user, Some(&q.from));
+    let handler = BotHandler::new(bot.clone(), msg.chat.id, user, locale, config, db);
+    let result = handler.handle_callback(&msg, callback).await;
+    let mut answer = bot.answer_callback_query(q.id);
+    match result {
+        Ok(Some(text)) => answer = answer.text(text),
+        Ok(None) => {}
+        Err(ref e) => {
+            log::error!("callback error: {:?}", e);
+            // callback answers are limited to 200 characters
+            let text = e
+                .user_message(handler.locale)
+                .chars()
+                .take(200)
+                .collect::<String>();
+            answer = answer.text(text).show_alert(true);
+        }
+    }
+    answer.await?;
     Ok(())
 }
 
 pub struct BotHandler {
     pub bot: Arc<Bot>,
     pub chat_id: ChatId,
+    pub user: database::User,
+    pub locale: Locale,
     pub config: Arc<Config>,
     pub db: Arc<database::Client>,
 }
@@ -126,53 +496,362 @@ impl BotHandler {
     pub fn new(
         bot: Arc<Bot>,
         chat_id: ChatId,
+        user: database::User,
+        locale: Locale,
         config: Arc<Config>,
         db: Arc<database::Client>,
     ) -> Self {
         BotHandler {
             bot,
             chat_id,
+            user,
+            locale,
             config,
             db,
         }
     }
-    pub async fn bot_help(&self) -> Result<()> {
+    pub fn t(&self, msg: Msg) -> &'static str {
+        self.locale.text(msg)
+    }
+    /// Log an error and tell the user what went wrong instead of "Failed.".
+    async fn reply_error(&self, context: &str, e: error::Error) -> Result<()> {
+        log::error!("{} error: {:?}", context, e);
         self.bot
-            .send_message(self.chat_id, Command::descriptions().to_string())
+            .send_message(self.chat_id, e.user_message(self.locale))
             .await?;
         Ok(())
     }
+    /// In group chats changes are limited to the chat's administrators.
+    pub async fn is_chat_admin(&self) -> bool {
+        if self.chat_id.is_user() {
+            return true;
+        }
+        match self
+            .bot
+            .get_chat_member(self.chat_id, UserId(self.user.id))
+            .await
+        {
+            Ok(member) => member.is_privileged(),
+            Err(e) => {
+                log::error!("get chat member error: {:?}", e);
+                false
+            }
+        }
+    }
+    /// Admins manage every bangumi, members only the ones they own.
+    pub fn can_manage(&self, id: u32) -> bool {
+        match self.user.role {
+            database::Role::Admin => true,
+            database::Role::Member => self
+                .db
+                .get_bangumi(id)
+                .ok()
+                .flatten()
+                .map(|b| b.owners.contains(&self.user.id))
+                .unwrap_or_default(),
+            database::Role::Viewer => false,
+        }
+    }
+    pub async fn bot_help(&self) -> Result<()> {
+        let text = if self.locale == Locale::En {
+            Command::descriptions().to_string()
+        } else {
+            let lines = Command::localized_commands(self.locale)
+                .into_iter()
+                .map(|c| format!("{} — {}", c.command, c.description))
+                .collect::<Vec<String>>();
+            format!("{}\n\n{}", self.t(Msg::HelpHeader), lines.join("\n"))
+        };
+        self.bot.send_message(self.chat_id, text).await?;
+        Ok(())
+    }
     pub async fn bangumi_list(&self) -> Result<()> {
         let bangumi = match self.db.get_bangumi_all() {
             Ok(b) => b,
             Err(e) => {
-                log::error!("database error: {:?}", e);
-                self.bot.send_message(self.chat_id, "Failed.").await?;
+                self.reply_error("database", e).await?;
                 return Ok(());
             }
         };
         if bangumi.is_empty() {
             self.bot
-                .send_message(self.chat_id, "no bangumi found.")
+                .send_message(self.chat_id, self.t(Msg::NoBangumi))
                 .await?;
         } else {
-            let text = bangumi
-                .iter()
-                .map(|b| format!("{}\n{}\n", b.id, b.title))
-                .collect::<Vec<String>>()
-                .join("\n");
-            self.bot.send_message(self.chat_id, text).await?;
+            let (text, keyboard) = self.list_view(&bangumi);
+            self.bot
+                .send_message(self.chat_id, text)
+                .reply_markup(keyboard)
+                .await?;
         }
         Ok(())
     }
-    pub async fn bangumi_add(&self, url: String) -> Result<()> {
-        if url.is_empty() {
+    fn list_view(&self, bangumi: &[database::Bangumi]) -> (String, InlineKeyboardMarkup) {
+        let text = bangumi
+            .iter()
+            .map(|b| format!("{}\n{}\n", b.id, b.title))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let keyboard = InlineKeyboardMarkup::new(bangumi.iter().map(|b| {
+            let mark = if b.enabled { "" } else { "⏸ " };
+            vec![Callback::Show(b.id).button(&format!("{}{}", mark, b.title))]
+        }));
+        (text, keyboard)
+    }
+    fn yes_no(&self, value: bool) -> &'static str {
+        self.t(if value { Msg::Yes } else { Msg::No })
+    }
+    fn card_view(&self, b: &database::Bangumi) -> (String, InlineKeyboardMarkup) {
+        let text = fill(
+            self.t(Msg::Card),
+            &[
+                &b.id,
+                &b.title,
+                &self.yes_no(b.enabled),
+                &b.downloaded.len(),
+            ],
+        );
+        let toggle = if b.enabled {
+            Callback::Disable(b.id).button(self.t(Msg::ButtonDisable))
+        } else {
+            Callback::Enable(b.id).button(self.t(Msg::ButtonEnable))
+        };
+        let keyboard = InlineKeyboardMarkup::new(vec![
+            vec![toggle, Callback::Info(b.id).button(self.t(Msg::ButtonInfo))],
+            vec![
+                Callback::Filters(b.id).button(self.t(Msg::ButtonFilters)),
+                Callback::Remove(b.id).button(self.t(Msg::ButtonRemove)),
+            ],
+            vec![Callback::List.button(self.t(Msg::ButtonBack))],
+        ]);
+        (text, keyboard)
+    }
+    fn info_text(&self, b: &database::Bangumi) -> String {
+        fill(
+            self.t(Msg::Info),
+            &[
+                &b.id,
+                &b.title,
+                &b.weekday,
+                &b.poster_url,
+                &b.rss_url,
+                &self.yes_no(b.enabled),
+                &format!("{:?}", b.mode),
+                &format!("{:?}", b.not_contains),
+                &b.downloaded.len(),
+                &format!("{:?}", b.poll),
+            ],
+        )
+    }
+    async fn edit(
+        &self,
+        message: &Message,
+        text: String,
+        keyboard: InlineKeyboardMarkup,
+    ) -> error::Result<Option<String>> {
+        if message.photo().is_some() {
             self.bot
-                .send_message(self.chat_id, "rss url required.")
+                .edit_message_caption(self.chat_id, message.id)
+                .caption(text)
+                .reply_markup(keyboard)
                 .await?;
-            return Ok(());
+        } else {
+            self.bot
+                .edit_message_text(self.chat_id, message.id, text)
+                .reply_markup(keyboard)
+                .await?;
+        }
+        Ok(None)
+    }
+    /// Handle a button press, returning a short notice to show instead of
+    /// editing the message for actions fired from notifications.
+    pub async fn handle_callback(
+        &self,
+        message: &Message,
+        callback: Callback,
+    ) -> error::Result<Option<String>> {
+        let back = |id: u32| {
+            InlineKeyboardMarkup::new(vec![vec![
+                Callback::Show(id).button(self.t(Msg::ButtonBack))
+            ]])
+        };
+        let id = match callback.id() {
+            Some(id) => id,
+            None => {
+                let bangumi = self.db.get_bangumi_all()?;
+                let (text, keyboard) = self.list_view(&bangumi);
+                let text = if text.is_empty() {
+                    self.t(Msg::NoBangumi).to_string()
+                } else {
+                    text
+                };
+                return self.edit(message, text, keyboard).await;
+            }
+        };
+        let read_only = matches!(
+            callback,
+            Callback::Show(_) | Callback::Info(_) | Callback::Filters(_)
+        );
+        let permitted = read_only || (self.can_manage(id) && self.is_chat_admin().await);
+        if !permitted {
+            return Ok(Some(self.t(Msg::PermissionDenied).to_string()));
+        }
+        match &callback {
+            Callback::Pause(_) => {
+                self.db.set_bangumi_enabled(id, false)?;
+                return Ok(Some(self.t(Msg::Paused).to_string()));
+            }
+            Callback::SkipGroup(_, group) => {
+                let b = self
+                    .db
+                    .get_bangumi(id)?
+                    .ok_or(database::DatabaseError::BangumiNotFound)?;
+                let mut not_contains = b.not_contains;
+                if !not_contains.contains(group) {
+                    not_contains.push(group.clone());
+                }
+                self.db.set_bangumi_not_contains(id, not_contains)?;
+                return Ok(Some(fill(self.t(Msg::GroupSkipped), &[group])));
+            }
+            Callback::Resolve(_, item, _, _) | Callback::IgnoreItem(_, item) => {
+                let item = match self.db.get_quarantine_item(*item)? {
+                    Some(item) if item.bangumi_id == id => item,
+                    _ => return Ok(Some(self.t(Msg::QuarantineNotFound).to_string())),
+                };
+                let answer = match callback {
+                    Callback::Resolve(_, _, season, episode) => {
+                        self.resolve(item, season, episode).await?
+                    }
+                    _ => {
+                        self.db.ignore_quarantine_item(&item)?;
+                        self.t(Msg::ItemIgnored).to_string()
+                    }
+                };
+                let text = format!("{}\n\n{}", message.text().unwrap_or_default(), answer);
+                return self
+                    .edit(message, text, InlineKeyboardMarkup::default())
+                    .await;
+            }
+            _ => {}
+        }
+        match &callback {
+            Callback::Enable(_) => self.db.set_bangumi_enabled(id, true),
+            Callback::Disable(_) => self.db.set_bangumi_enabled(id, false),
+            Callback::ClearFilters(_) => self.db.set_bangumi_not_contains(id, vec![]),
+            _ => Ok(()),
+        }?;
+        if let Callback::ConfirmRemove(_, mode) = callback {
+            self.remove(id, mode).await?;
+            let bangumi = self.db.get_bangumi_all()?;
+            let (text, keyboard) = self.list_view(&bangumi);
+            let text = format!("{}\n\n{}", fill(self.t(Msg::Removed), &[&id]), text);
+            return self.edit(message, text, keyboard).await;
+        }
+        let b = match self.db.get_bangumi(id)? {
+            Some(b) => b,
+            None => {
+                let keyboard = InlineKeyboardMarkup::new(vec![vec![
+                    Callback::List.button(self.t(Msg::ButtonBack))
+                ]]);
+                return self
+                    .edit(message, self.t(Msg::BangumiNotFound).to_string(), keyboard)
+                    .await;
+            }
+        };
+        let (text, keyboard) = match callback {
+            Callback::Info(_) => (self.info_text(&b), back(id)),
+            Callback::Filters(_) => (
+                fill(
+                    self.t(Msg::FiltersHint),
+                    &[&b.title, &format!("{:?}", b.not_contains), &b.id],
+                ),
+                InlineKeyboardMarkup::new(vec![
+                    vec![Callback::ClearFilters(id).button(self.t(Msg::ButtonClearFilters))],
+                    vec![Callback::Show(id).button(self.t(Msg::ButtonBack))],
+                ]),
+            ),
+            Callback::Remove(_) => self.remove_view(&b),
+            _ => self.card_view(&b),
+        };
+        self.edit(message, text, keyboard).await
+    }
+    /// Ask what to remove along with a subscription.
+    fn remove_view(&self, b: &database::Bangumi) -> (String, InlineKeyboardMarkup) {
+        let text = fill(
+            self.t(Msg::ConfirmRemove),
+            &[&b.title, &organizer::torrent_hashes(b).len()],
+        );
+        let mut rows = organizer::RemoveMode::ALL
+            .into_iter()
+            .map(|mode| {
+                let label = match mode {
+                    organizer::RemoveMode::KeepFiles => Msg::ButtonRemoveKeepFiles,
+                    organizer::RemoveMode::Torrents => Msg::ButtonRemoveTorrents,
+                    organizer::RemoveMode::TorrentsAndData => Msg::ButtonRemoveData,
+                    organizer::RemoveMode::Library => Msg::ButtonRemoveLibrary,
+                };
+                vec![Callback::ConfirmRemove(b.id, mode).button(self.t(label))]
+            })
+            .collect::<Vec<_>>();
+        rows.push(vec![Callback::Show(b.id).button(self.t(Msg::ButtonCancel))]);
+        (text, InlineKeyboardMarkup::new(rows))
+    }
+    async fn remove(&self, id: u32, mode: organizer::RemoveMode) -> error::Result<()> {
+        let b = self
+            .db
+            .get_bangumi(id)?
+            .ok_or(database::DatabaseError::BangumiNotFound)?;
+        organizer::remove_bangumi(&self.db, &b, &self.config.lib_dir, mode).await?;
+        self.db.delete_retry_tasks(id)?;
+        self.db.delete_quarantine(id)?;
+        for rule in self.db.get_parse_rules()? {
+            if rule.scope == database::RuleScope::Bangumi(id) {
+                self.db.delete_parse_rule(rule.id)?;
+                title_parser::forget_rule(&rule.pattern);
+            }
+        }
+        self.db.delete_bangumi(id)?;
+        Ok(())
+    }
+    pub async fn bangumi_add(&self, args: String) -> Result<()> {
+        let mut args = args.split_whitespace();
+        let url = match args.next() {
+            Some(url) => url.to_string(),
+            None => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::RssUrlRequired))
+                    .await?;
+                return Ok(());
+            }
+        };
+        let mode: database::SubscribeMode = match args.next().unwrap_or_default().parse() {
+            Ok(mode) => mode,
+            Err(_) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::InvalidMode))
+                    .await?;
+                return Ok(());
+            }
+        };
+        self.bot
+            .send_message(self.chat_id, self.t(Msg::Fetching))
+            .await?;
+        let mut ignored = vec![];
+        if mode == database::SubscribeMode::OnlyNew {
+            match mikan::MikanRss::from_url(&url)
+                .set_proxy(self.config.proxy.clone())?
+                .fetch()
+                .await
+            {
+                Ok(rss) => {
+                    ignored = rss.items.into_iter().map(|ep| ep.torrent_hash).collect();
+                }
+                Err(e) => {
+                    self.reply_error("RSS", e).await?;
+                    return Ok(());
+                }
+            }
         }
-        self.bot.send_message(self.chat_id, "fetching...").await?;
         match mikan::MikanParser::new()
             .set_proxy(self.config.proxy.clone())?
             .from_rss_url(&url)
@@ -185,27 +864,30 @@ impl BotHandler {
                     weekday: b.weekday,
                     poster_url: b.poster_url.clone(),
                     downloaded: vec![],
+                    episodes: vec![],
+                    mode,
+                    ignored,
+                    owners: vec![self.user.id],
+                    chats: vec![self.chat_id.0],
+                    channel: None,
+                    poll: database::PollMode::Auto,
+                    feed: Default::default(),
+                    seeding: Default::default(),
                     rss_url: url,
                     enabled: true,
                     not_contains: self.config.not_contains.clone(),
                 };
                 if let Ok(true) = self.db.bangumi_exists(b.id) {
                     self.bot
-                        .send_message(self.chat_id, "Bangumi already exists.")
+                        .send_message(self.chat_id, self.t(Msg::BangumiExists))
                         .await?;
                     return Ok(());
                 }
                 match self.db.insert_bangumi(bangumi) {
                     Ok(_) => {
-                        utils::ensure_dir(&self.config.tmp_dir).await?;
-                        let poster_path = format!(
-                            "{}/{}",
-                            self.config.tmp_dir,
-                            b.poster_url.split('/').last().unwrap()
-                        );
-                        utils::download_file(
+                        let poster_path = utils::cached_file(
                             &b.poster_url,
-                            &poster_path,
+                            &format!("{}/posters", self.config.tmp_dir),
                             self.config.proxy.clone(),
                         )
                         .await?;
@@ -214,29 +896,37 @@ impl BotHandler {
                         self.bot
                             .send_message(self.chat_id, format!("{}\n{}", b.id, b.title))
                             .await?;
-                        self.bot.send_message(self.chat_id, "Success.").await?;
+                        self.bot
+                            .send_message(self.chat_id, self.t(Msg::Success))
+                            .await?;
                     }
                     Err(e) => {
-                        log::error!("database error: {:?}", e);
-                        self.bot.send_message(self.chat_id, "Failed.").await?;
+                        self.reply_error("database", e).await?;
                     }
                 }
             }
             Err(e) => {
-                log::error!("RSS error: {:?}", e);
-                self.bot.send_message(self.chat_id, "Failed.").await?;
+                self.reply_error("RSS", e).await?;
             }
         }
         Ok(())
     }
     pub async fn bangumi_remove(&self, id: u32) -> Result<()> {
-        match self.db.delete_bangumi(id) {
-            Ok(_) => {
-                self.bot.send_message(self.chat_id, "Success.").await?;
+        match self.db.get_bangumi(id) {
+            Ok(Some(b)) => {
+                let (text, keyboard) = self.remove_view(&b);
+                self.bot
+                    .send_message(self.chat_id, text)
+                    .reply_markup(keyboard)
+                    .await?;
+            }
+            Ok(None) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::BangumiNotFound))
+                    .await?;
             }
             Err(e) => {
-                log::error!("database error: {:?}", e);
-                self.bot.send_message(self.chat_id, "Failed.").await?;
+                self.reply_error("database", e).await?;
             }
         }
         Ok(())
@@ -245,20 +935,17 @@ impl BotHandler {
         match self.db.get_bangumi(id) {
             Ok(b) => {
                 if let Some(b) = b {
-                    let text = format!(
-                        "id: {}\ntitle: {}\nweekday: {}\nposter: {}\nurl: {}\nenabled: {}\nnot contains: {:?}\ndownloaded: {}",
-                        b.id, b.title, b.weekday, b.poster_url, b.rss_url, b.enabled, b.not_contains, b.downloaded.len()
-                    );
-                    self.bot.send_message(self.chat_id, text).await?;
+                    self.bot
+                        .send_message(self.chat_id, self.info_text(&b))
+                        .await?;
                 } else {
                     self.bot
-                        .send_message(self.chat_id, "Bangumi not found.")
+                        .send_message(self.chat_id, self.t(Msg::BangumiNotFound))
                         .await?;
                 }
             }
             Err(e) => {
-                log::error!("database error: {:?}", e);
-                self.bot.send_message(self.chat_id, "Failed.").await?;
+                self.reply_error("database", e).await?;
             }
         }
         Ok(())
@@ -266,11 +953,12 @@ impl BotHandler {
     pub async fn bangumi_enable(&self, id: u32) -> Result<()> {
         match self.db.set_bangumi_enabled(id, true) {
             Ok(_) => {
-                self.bot.send_message(self.chat_id, "Success.").await?;
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::Success))
+                    .await?;
             }
             Err(e) => {
-                log::error!("database error: {:?}", e);
-                self.bot.send_message(self.chat_id, "Failed.").await?;
+                self.reply_error("database", e).await?;
             }
         }
         Ok(())
@@ -278,11 +966,12 @@ impl BotHandler {
     pub async fn bangumi_disable(&self, id: u32) -> Result<()> {
         match self.db.set_bangumi_enabled(id, false) {
             Ok(_) => {
-                self.bot.send_message(self.chat_id, "Success.").await?;
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::Success))
+                    .await?;
             }
             Err(e) => {
-                log::error!("database error: {:?}", e);
-                self.bot.send_message(self.chat_id, "Failed.").await?;
+                self.reply_error("database", e).await?;
             }
         }
         Ok(())
@@ -294,13 +983,995 @@ impl BotHandler {
         }
         match self.db.set_bangumi_not_contains(id, words) {
             Ok(_) => {
-                self.bot.send_message(self.chat_id, "Success.").await?;
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::Success))
+                    .await?;
+            }
+            Err(e) => {
+                self.reply_error("database", e).await?;
+            }
+        }
+        Ok(())
+    }
+    pub async fn library_scan(&self, arg: String) -> Result<()> {
+        // downloads from before episodes were recorded, skipped when
+        // qBittorrent is unreachable
+        let backfilled = match library::backfill_episodes(&self.db).await {
+            Ok(n) => n,
+            Err(e) => {
+                log::warn!("episode backfill error: {:?}", e);
+                0
+            }
+        };
+        let report = match library::scan(&self.db, &self.config.lib_dir) {
+            Ok(r) => r,
+            Err(e) => {
+                self.reply_error("scan", e).await?;
+                return Ok(());
+            }
+        };
+        let relative = |p: &std::path::PathBuf| {
+            p.strip_prefix(&self.config.lib_dir)
+                .unwrap_or(p)
+                .to_string_lossy()
+                .to_string()
+        };
+        let mut sections = vec![fill(
+            self.t(Msg::ScanSummary),
+            &[
+                &report.missing.len(),
+                &report.extra.len(),
+                &report.unknown.len(),
+                &report.untracked.len(),
+            ],
+        )];
+        if backfilled > 0 {
+            sections.push(fill(self.t(Msg::ScanBackfilled), &[&backfilled]));
+        }
+        let lists = [
+            (
+                self.t(Msg::ScanMissing),
+                report
+                    .missing
+                    .iter()
+                    .map(|m| {
+                        format!(
+                            "{} - {} S{:02}E{:02}",
+                            m.bangumi_id, m.title, m.episode.season, m.episode.episode
+                        )
+                    })
+                    .collect::<Vec<String>>(),
+            ),
+            (
+                self.t(Msg::ScanExtra),
+                report.extra.iter().map(relative).collect(),
+            ),
+            (
+                self.t(Msg::ScanUnknown),
+                report.unknown.iter().map(relative).collect(),
+            ),
+            (
+                self.t(Msg::ScanUntracked),
+                report
+                    .untracked
+                    .iter()
+                    .map(|(id, title, hash)| format!("{} - {} {}", id, title, hash))
+                    .collect(),
+            ),
+        ];
+        for (name, items) in lists.iter() {
+            if items.is_empty() {
+                continue;
+            }
+            let mut text = format!(
+                "{}:\n{}",
+                name,
+                items
+                    .iter()
+                    .take(20)
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            );
+            if items.len() > 20 {
+                text.push('\n');
+                text.push_str(&fill(self.t(Msg::AndMore), &[&(items.len() - 20)]));
+            }
+            sections.push(text);
+        }
+        if arg.trim() == "requeue" {
+            match library::requeue_missing(&self.db, &report) {
+                Ok(n) => sections.push(fill(self.t(Msg::Requeued), &[&n])),
+                Err(e) => {
+                    log::error!("database error: {:?}", e);
+                    sections.push(format!(
+                        "{} {}",
+                        self.t(Msg::RequeueFailed),
+                        e.user_message(self.locale)
+                    ));
+                }
+            }
+        }
+        self.bot
+            .send_message(self.chat_id, sections.join("\n\n"))
+            .await?;
+        Ok(())
+    }
+    pub async fn bangumi_backfill(&self, id: u32, range: String) -> Result<()> {
+        let bounds = range
+            .split_once('-')
+            .map(|(from, to)| (from.trim().parse::<i16>(), to.trim().parse::<i16>()));
+        let (from, to) = match bounds {
+            Some((Ok(from), Ok(to))) if from <= to => (from, to),
+            _ => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::UsageBackfill))
+                    .await?;
+                return Ok(());
+            }
+        };
+        let bangumi = match self.db.get_bangumi(id) {
+            Ok(Some(b)) => b,
+            Ok(None) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::BangumiNotFound))
+                    .await?;
+                return Ok(());
+            }
+            Err(e) => {
+                self.reply_error("database", e).await?;
+                return Ok(());
+            }
+        };
+        self.bot
+            .send_message(self.chat_id, self.t(Msg::Fetching))
+            .await?;
+        match updater::backfill(
+            bangumi,
+            from,
+            to,
+            self.db.clone(),
+            self.config.clone(),
+            self.bot.clone(),
+        )
+        .await
+        {
+            Ok(queued) if queued.is_empty() => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::NothingToBackfill))
+                    .await?;
+            }
+            Ok(queued) => {
+                let names = queued
+                    .iter()
+                    .map(|q| q.save_name.clone())
+                    .collect::<Vec<String>>();
+                self.bot
+                    .send_message(
+                        self.chat_id,
+                        fill(self.t(Msg::BackfillQueued), &[&names.join("\n")]),
+                    )
+                    .await?;
+            }
+            Err(e) => {
+                self.reply_error("backfill", e).await?;
+            }
+        }
+        Ok(())
+    }
+    pub async fn episode_get(&self, url: String) -> Result<()> {
+        let url = url.trim();
+        if !url.contains("/Home/Episode/") {
+            self.bot
+                .send_message(self.chat_id, self.t(Msg::EpisodeUrlRequired))
+                .await?;
+            return Ok(());
+        }
+        self.bot
+            .send_message(self.chat_id, self.t(Msg::Fetching))
+            .await?;
+        let info = match mikan::MikanParser::new()
+            .set_proxy(self.config.proxy.clone())?
+            .from_url(url)
+            .await
+        {
+            Ok(info) => info,
+            Err(e) => {
+                self.reply_error("Mikan", e).await?;
+                return Ok(());
+            }
+        };
+        let (magnet, title) = match (info.magnet.as_ref(), info.episode_title.as_ref()) {
+            (Some(magnet), Some(title)) => (magnet, title),
+            _ => {
+                self.reply_error("Mikan", mikan::MikanError::MagnetNotFound.into())
+                    .await?;
+                return Ok(());
+            }
+        };
+        let ep_info = match self
+            .db
+            .get_parse_rules()
+            .and_then(|rules| updater::explain_parse(None, &rules, title))
+        {
+            Ok((ep_info, _)) => ep_info,
+            Err(e) => {
+                log::error!("parse error: {:?}", e);
+                self.bot
+                    .send_message(self.chat_id, fill(self.t(Msg::CannotParseTitle), &[title]))
+                    .await?;
+                return Ok(());
+            }
+        };
+        let bangumi = match self.db.get_bangumi(info.id) {
+            Ok(Some(b)) if !self.can_manage(b.id) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::PermissionDenied))
+                    .await?;
+                return Ok(());
+            }
+            Ok(Some(b)) => b,
+            Ok(None) => {
+                let b = database::Bangumi {
+                    id: info.id,
+                    title: info.title.clone(),
+                    weekday: info.weekday,
+                    poster_url: info.poster_url.clone(),
+                    downloaded: vec![],
+                    rss_url: mikan::MikanParser::rss_url(info.id),
+                    enabled: false,
+                    not_contains: self.config.not_contains.clone(),
+                    episodes: vec![],
+                    mode: database::SubscribeMode::All,
+                    ignored: vec![],
+                    owners: vec![self.user.id],
+                    chats: vec![self.chat_id.0],
+                    channel: None,
+                    poll: database::PollMode::Auto,
+                    feed: Default::default(),
+                    seeding: Default::default(),
+                };
+                if let Err(e) = self.db.insert_bangumi(b.clone()) {
+                    self.reply_error("database", e).await?;
+                    return Ok(());
+                }
+                b
+            }
+            Err(e) => {
+                self.reply_error("database", e).await?;
+                return Ok(());
+            }
+        };
+        let hash = downloader::QbitDownloader::magnet_to_hash(magnet);
+        if bangumi.downloaded.contains(&hash) {
+            self.bot
+                .send_message(self.chat_id, self.t(Msg::EpisodeDownloaded))
+                .await?;
+            return Ok(());
+        }
+        match updater::download_magnet(&bangumi, magnet, title, &ep_info, &self.db, &self.config)
+            .await
+        {
+            Ok(queued) => {
+                self.bot
+                    .send_message(
+                        self.chat_id,
+                        fill(self.t(Msg::EpisodeQueued), &[&queued.save_name]),
+                    )
+                    .await?;
+            }
+            Err(e) => {
+                self.reply_error("download", e).await?;
+            }
+        }
+        Ok(())
+    }
+    pub async fn bangumi_follow(&self, id: u32, follow: bool) -> Result<()> {
+        match self.db.follow_bangumi(id, self.chat_id.0, follow) {
+            Ok(_) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::Success))
+                    .await?;
+            }
+            Err(e) => {
+                self.reply_error("database", e).await?;
+            }
+        }
+        Ok(())
+    }
+    pub async fn chat_notify(&self, mode: String) -> Result<()> {
+        let notify: database::NotifyMode = match mode.parse() {
+            Ok(notify) => notify,
+            Err(_) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::UsageNotify))
+                    .await?;
+                return Ok(());
+            }
+        };
+        let locale = self
+            .db
+            .get_chat(self.chat_id.0)
+            .ok()
+            .flatten()
+            .and_then(|c| c.locale);
+        let chat = database::Chat {
+            id: self.chat_id.0,
+            notify,
+            locale,
+        };
+        match self.db.set_chat(chat) {
+            Ok(_) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::Success))
+                    .await?;
+            }
+            Err(e) => {
+                self.reply_error("database", e).await?;
+            }
+        }
+        Ok(())
+    }
+    pub async fn user_list(&self) -> Result<()> {
+        match self.db.get_users() {
+            Ok(users) => {
+                let text = users
+                    .iter()
+                    .map(|u| format!("{} {:?}", u.id, u.role))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                self.bot.send_message(self.chat_id, text).await?;
+            }
+            Err(e) => {
+                self.reply_error("database", e).await?;
+            }
+        }
+        Ok(())
+    }
+    pub async fn user_set(&self, id: u64, role: String) -> Result<()> {
+        if id == self.user.id {
+            self.bot
+                .send_message(self.chat_id, self.t(Msg::OwnRole))
+                .await?;
+            return Ok(());
+        }
+        let result = if role.trim() == "remove" {
+            self.db.delete_user(id)
+        } else {
+            match role.parse::<database::Role>() {
+                Ok(role) => {
+                    let locale = self.db.get_user(id).ok().flatten().and_then(|u| u.locale);
+                    self.db.set_user(database::User { id, role, locale })
+                }
+                Err(_) => {
+                    self.bot
+                        .send_message(self.chat_id, self.t(Msg::UsageUser))
+                        .await?;
+                    return Ok(());
+                }
+            }
+        };
+        match result {
+            Ok(_) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::Success))
+                    .await?;
+            }
+            Err(e) => {
+                self.reply_error("database", e).await?;
+            }
+        }
+        Ok(())
+    }
+    pub async fn bangumi_channel(&self, id: u32, channel: String) -> Result<()> {
+        let channel = match channel.trim() {
+            "none" => None,
+            c if c.starts_with('@') || c.parse::<i64>().is_ok() => Some(c.to_string()),
+            _ => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::UsageChannel))
+                    .await?;
+                return Ok(());
+            }
+        };
+        match self.db.set_bangumi_channel(id, channel) {
+            Ok(_) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::Success))
+                    .await?;
+            }
+            Err(e) => {
+                self.reply_error("database", e).await?;
+            }
+        }
+        Ok(())
+    }
+    pub async fn bangumi_poll(&self, id: u32, poll: String) -> Result<()> {
+        let poll: database::PollMode = match poll.parse() {
+            Ok(poll) => poll,
+            Err(_) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::UsagePoll))
+                    .await?;
+                return Ok(());
+            }
+        };
+        match self.db.set_bangumi_poll(id, poll) {
+            Ok(_) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::Success))
+                    .await?;
+            }
+            Err(e) => {
+                self.reply_error("database", e).await?;
+            }
+        }
+        Ok(())
+    }
+    pub async fn bangumi_check(&self, id: String) -> Result<()> {
+        let id = match id.trim() {
+            "" => None,
+            id => match id.parse::<u32>() {
+                Ok(id) => Some(id),
+                Err(_) => {
+                    self.bot
+                        .send_message(self.chat_id, self.t(Msg::UsageCheck))
+                        .await?;
+                    return Ok(());
+                }
+            },
+        };
+        self.bot
+            .send_message(self.chat_id, self.t(Msg::Fetching))
+            .await?;
+        let results = match updater::check(
+            id.map(|id| vec![id]),
+            self.db.clone(),
+            self.config.clone(),
+            self.bot.clone(),
+        )
+        .await
+        {
+            Ok(results) => results,
+            Err(e) => {
+                self.reply_error("check", e).await?;
+                return Ok(());
+            }
+        };
+        if results.is_empty() {
+            let text = match id {
+                Some(_) => self.t(Msg::BangumiNotFound),
+                None => self.t(Msg::NothingToCheck),
+            };
+            self.bot.send_message(self.chat_id, text).await?;
+            return Ok(());
+        }
+        let sections = results
+            .iter()
+            .map(|(b, result)| match result {
+                Ok(report) => self.check_text(b, report),
+                Err(e) => format!("{} {}\n{}", b.id, b.title, e.user_message(self.locale)),
+            })
+            .collect::<Vec<String>>();
+        // telegram messages are limited to 4096 characters
+        let mut text = String::new();
+        for section in sections {
+            if !text.is_empty() && text.chars().count() + section.chars().count() > 4000 {
+                self.bot.send_message(self.chat_id, text).await?;
+                text = String::new();
+            }
+            if !text.is_empty() {
+                text.push_str("\n\n");
+            }
+            text.push_str(&section.chars().take(4000).collect::<String>());
+        }
+        self.bot.send_message(self.chat_id, text).await?;
+        Ok(())
+    }
+    fn check_text(&self, b: &database::Bangumi, report: &updater::UpdateReport) -> String {
+        let mut lines = vec![fill(
+            self.t(Msg::CheckSummary),
+            &[
+                &b.id,
+                &b.title,
+                &report.found,
+                &report.queued.len(),
+                &report.skipped.len(),
+            ],
+        )];
+        if report.unchanged {
+            lines.push(self.t(Msg::CheckUnchanged).to_string());
+        }
+        for q in report.queued.iter() {
+            lines.push(format!("+ {}", q.save_name));
+            if let Some((season, episode)) = q.file_mismatch {
+                lines.push(updater::mismatch_text(self.locale, q, season, episode));
+            }
+            for issue in q.issues.iter() {
+                lines.push(updater::issue_text(self.locale, q, issue));
+            }
+        }
+        for task in report.failed.iter() {
+            lines.push(format!("! {}", self.retry_text(task)));
+        }
+        // items seen in earlier checks would repeat in every report
+        let (seen, skipped): (Vec<_>, Vec<_>) = report.skipped.iter().partition(|(_, r)| {
+            matches!(
+                r,
+                updater::SkipReason::Downloaded
+                    | updater::SkipReason::Ignored
+                    | updater::SkipReason::Seen
+            )
+        });
+        for (title, reason) in skipped.iter().take(20) {
+            lines.push(format!("- {}: {}", title, reason.message(self.locale)));
+        }
+        if skipped.len() > 20 {
+            lines.push(fill(self.t(Msg::AndMore), &[&(skipped.len() - 20)]));
+        }
+        if !seen.is_empty() {
+            lines.push(fill(self.t(Msg::CheckHidden), &[&seen.len()]));
+        }
+        lines.join("\n")
+    }
+    fn retry_text(&self, task: &database::RetryTask) -> String {
+        if task.gave_up {
+            return fill(
+                self.t(Msg::RetryGaveUp),
+                &[&task.title, &task.attempts, &task.last_error],
+            );
+        }
+        let minutes = (task.next_try - chrono::Utc::now().timestamp()).max(0) / 60;
+        fill(
+            self.t(Msg::RetryPending),
+            &[&task.title, &task.attempts, &minutes, &task.last_error],
+        )
+    }
+    /// Without an id list the failed downloads, with one make those of the
+    /// bangumi due and check it right away.
+    pub async fn bangumi_retry(&self, id: String) -> Result<()> {
+        let id = match id.trim() {
+            "" => None,
+            id => match id.parse::<u32>() {
+                Ok(id) => Some(id),
+                Err(_) => {
+                    self.bot
+                        .send_message(self.chat_id, self.t(Msg::UsageRetry))
+                        .await?;
+                    return Ok(());
+                }
+            },
+        };
+        let id = match id {
+            Some(id) => id,
+            None => {
+                let tasks = match self.db.get_retry_tasks(None) {
+                    Ok(tasks) => tasks,
+                    Err(e) => return self.reply_error("database", e).await,
+                };
+                let text = match tasks.is_empty() {
+                    true => self.t(Msg::NoRetries).to_string(),
+                    false => tasks
+                        .iter()
+                        .map(|t| format!("{} {}", t.bangumi_id, self.retry_text(t)))
+                        .collect::<Vec<String>>()
+                        .join("\n\n")
+                        .chars()
+                        .take(4000)
+                        .collect(),
+                };
+                self.bot.send_message(self.chat_id, text).await?;
+                return Ok(());
+            }
+        };
+        if !self.can_manage(id) {
+            self.bot
+                .send_message(self.chat_id, self.t(Msg::PermissionDenied))
+                .await?;
+            return Ok(());
+        }
+        match self.db.reset_retry_tasks(id) {
+            Ok(0) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::NoRetries))
+                    .await?;
+                Ok(())
+            }
+            Ok(count) => {
+                self.bot
+                    .send_message(self.chat_id, fill(self.t(Msg::RetryReset), &[&count]))
+                    .await?;
+                self.bangumi_check(id.to_string()).await
+            }
+            Err(e) => self.reply_error("database", e).await,
+        }
+    }
+    /// Download a quarantined item as the given episode, learning a rule for
+    /// similar titles when the episode is one of the numbers in the title.
+    async fn resolve(
+        &self,
+        item: database::QuarantineItem,
+        season: i8,
+        episode: i16,
+    ) -> error::Result<String> {
+        let b = self
+            .db
+            .get_bangumi(item.bangumi_id)?
+            .ok_or(database::DatabaseError::BangumiNotFound)?;
+        let rule = title_parser::LearnedRule::learn(&item.title, season, episode);
+        let ep_info =
+            rule.as_ref()
+                .and_then(|r| r.apply(&item.title))
+                .unwrap_or(title_parser::ParseResult {
+                    season,
+                    episode,
+                    ..Default::default()
+                });
+        let ep = mikan::RssEpisode {
+            title: item.title.clone(),
+            link: item.link.clone(),
+            description: String::new(),
+            torrent_url: item.torrent_url.clone(),
+            torrent_hash: item.hash.clone(),
+            guid: item.hash.clone(),
+            pub_date: None,
+        };
+        let queued = updater::download_episode(&b, &ep, &ep_info, &self.db, &self.config).await?;
+        self.db.delete_quarantine_item(item.id)?;
+        let mut text = fill(self.t(Msg::EpisodeQueued), &[&queued.save_name]);
+        if let Some(rule) = rule {
+            self.db.learn_parse_rule(b.id, rule)?;
+            text = format!("{}\n{}", text, self.t(Msg::RuleLearned));
+        }
+        Ok(text)
+    }
+    /// Without arguments list the quarantined items, otherwise give the
+    /// season and episode of one or ignore it.
+    pub async fn quarantine_resolve(&self, args: String) -> Result<()> {
+        let args = args.split_whitespace().collect::<Vec<&str>>();
+        if args.is_empty() {
+            let items = match self.db.get_quarantine(None) {
+                Ok(items) => items,
+                Err(e) => return self.reply_error("database", e).await,
+            };
+            let text = match items.is_empty() {
+                true => self.t(Msg::NoQuarantine).to_string(),
+                false => items
+                    .iter()
+                    .map(|i| format!("{} [{}] {}", i.id, i.bangumi_id, i.title))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+                    .chars()
+                    .take(4000)
+                    .collect(),
+            };
+            self.bot.send_message(self.chat_id, text).await?;
+            return Ok(());
+        }
+        let answer = match args.as_slice() {
+            [id, "ignore"] => id.parse::<u32>().ok().map(|id| (id, None)),
+            [id, season, episode] => match (id.parse(), season.parse(), episode.parse()) {
+                (Ok(id), Ok(season), Ok(episode)) => Some((id, Some((season, episode)))),
+                _ => None,
+            },
+            _ => None,
+        };
+        let (id, answer) = match answer {
+            Some(answer) => answer,
+            None => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::UsageResolve))
+                    .await?;
+                return Ok(());
+            }
+        };
+        let item = match self.db.get_quarantine_item(id) {
+            Ok(Some(item)) => item,
+            Ok(None) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::QuarantineNotFound))
+                    .await?;
+                return Ok(());
+            }
+            Err(e) => return self.reply_error("database", e).await,
+        };
+        if !self.can_manage(item.bangumi_id) {
+            self.bot
+                .send_message(self.chat_id, self.t(Msg::PermissionDenied))
+                .await?;
+            return Ok(());
+        }
+        let result = match answer {
+            Some((season, episode)) => self.resolve(item, season, episode).await,
+            None => self
+                .db
+                .ignore_quarantine_item(&item)
+                .map(|_| self.t(Msg::ItemIgnored).to_string()),
+        };
+        match result {
+            Ok(text) => {
+                self.bot.send_message(self.chat_id, text).await?;
+            }
+            Err(e) => self.reply_error("resolve", e).await?,
+        }
+        Ok(())
+    }
+    fn scope_text(&self, scope: &database::RuleScope) -> String {
+        match scope {
+            database::RuleScope::Fansub(fansub) => fill(self.t(Msg::ScopeFansub), &[fansub]),
+            database::RuleScope::Bangumi(id) => fill(self.t(Msg::ScopeBangumi), &[id]),
+        }
+    }
+    /// Rules for a fansub affect every subscription and are left to admins.
+    fn can_edit_rule(&self, scope: &database::RuleScope) -> bool {
+        match scope {
+            database::RuleScope::Fansub(_) => self.user.role == database::Role::Admin,
+            database::RuleScope::Bangumi(id) => self.can_manage(*id),
+        }
+    }
+    pub async fn parse_rule(&self, args: String) -> Result<()> {
+        let mut parts = args.trim().splitn(3, char::is_whitespace);
+        let result = match (parts.next(), parts.next(), parts.next()) {
+            (Some(""), None, None) => match self.db.get_parse_rules() {
+                Ok(rules) if rules.is_empty() => Ok(self.t(Msg::NoParseRules).to_string()),
+                Ok(rules) => Ok(rules
+                    .iter()
+                    .map(|r| format!("{} [{}] {}", r.id, self.scope_text(&r.scope), r.pattern))
+                    .collect::<Vec<String>>()
+                    .join("\n")),
+                Err(e) => Err(e),
+            },
+            (Some("add"), Some(scope), Some(pattern)) if !pattern.trim().is_empty() => {
+                let scope = match scope.parse::<u32>() {
+                    Ok(id) => database::RuleScope::Bangumi(id),
+                    Err(_) => database::RuleScope::Fansub(scope.to_string()),
+                };
+                if !self.can_edit_rule(&scope) {
+                    self.bot
+                        .send_message(self.chat_id, self.t(Msg::PermissionDenied))
+                        .await?;
+                    return Ok(());
+                }
+                let pattern = pattern.trim().to_string();
+                title_parser::validate_rule(&pattern)
+                    .and_then(|_| {
+                        self.db.insert_parse_rule(database::ParseRule {
+                            id: 0,
+                            scope,
+                            pattern,
+                            learned: None,
+                        })
+                    })
+                    .map(|rule| fill(self.t(Msg::ParseRuleAdded), &[&rule.id]))
+            }
+            (Some("remove"), Some(id), None) if id.parse::<u32>().is_ok() => {
+                let id = id.parse::<u32>().unwrap_or_default();
+                let rule = match self.db.get_parse_rules() {
+                    Ok(rules) => rules.into_iter().find(|r| r.id == id),
+                    Err(e) => return self.reply_error("database", e).await,
+                };
+                match rule {
+                    Some(rule) if !self.can_edit_rule(&rule.scope) => {
+                        Ok(self.t(Msg::PermissionDenied).to_string())
+                    }
+                    Some(rule) => self.db.delete_parse_rule(rule.id).map(|_| {
+                        title_parser::forget_rule(&rule.pattern);
+                        self.t(Msg::Success).to_string()
+                    }),
+                    None => Ok(self.t(Msg::ParseRuleNotFound).to_string()),
+                }
+            }
+            _ => Ok(self.t(Msg::UsageParseRule).to_string()),
+        };
+        match result {
+            Ok(text) => {
+                self.bot
+                    .send_message(self.chat_id, text.chars().take(4000).collect::<String>())
+                    .await?;
+            }
+            Err(e) => self.reply_error("parse rule", e).await?,
+        }
+        Ok(())
+    }
+    /// Without a bangumi show the global seeding policy, otherwise show or
+    /// set the policy of the bangumi.
+    pub async fn bangumi_seed(&self, args: String) -> Result<()> {
+        let (id, policy) = match args.trim().split_once(char::is_whitespace) {
+            Some((id, policy)) => (id, Some(policy.trim())),
+            None => (args.trim(), None),
+        };
+        if id.is_empty() {
+            let text = fill(self.t(Msg::SeedingGlobal), &[&self.config.seeding]);
+            self.bot.send_message(self.chat_id, text).await?;
+            return Ok(());
+        }
+        let b = match id.parse::<u32>().map(|id| self.db.get_bangumi(id)) {
+            Ok(Ok(Some(b))) => b,
+            Ok(Ok(None)) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::BangumiNotFound))
+                    .await?;
+                return Ok(());
+            }
+            Ok(Err(e)) => return self.reply_error("database", e).await,
+            Err(_) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::UsageSeed))
+                    .await?;
+                return Ok(());
+            }
+        };
+        let policy = match policy {
+            None => b.seeding.clone(),
+            Some(_) if !self.can_manage(b.id) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::PermissionDenied))
+                    .await?;
+                return Ok(());
+            }
+            Some(policy) => match policy
+                .parse::<seeding::SeedingPolicy>()
+                .map_err(error::Error::from)
+                .and_then(|policy| {
+                    self.db.set_bangumi_seeding(b.id, &policy)?;
+                    Ok(policy)
+                }) {
+                Ok(policy) => policy,
+                Err(e) => return self.reply_error("seeding", e).await,
+            },
+        };
+        let text = fill(
+            self.t(Msg::SeedingPolicy),
+            &[&b.title, &policy, &policy.or(&self.config.seeding)],
+        );
+        self.bot.send_message(self.chat_id, text).await?;
+        Ok(())
+    }
+    /// Show which rule recognizes a title and what it extracts, using the
+    /// rules of a subscription when the title starts with its id.
+    pub async fn test_parse(&self, args: String) -> Result<()> {
+        let args = args.trim();
+        if args.is_empty() {
+            self.bot
+                .send_message(self.chat_id, self.t(Msg::UsageTestParse))
+                .await?;
+            return Ok(());
+        }
+        let (bangumi, title) = match args.split_once(char::is_whitespace) {
+            Some((id, title)) => match id.parse::<u32>().ok().map(|id| self.db.get_bangumi(id)) {
+                Some(Ok(Some(b))) => (Some(b), title.trim()),
+                _ => (None, args),
+            },
+            None => (None, args),
+        };
+        let result = self
+            .db
+            .get_parse_rules()
+            .and_then(|rules| updater::explain_parse(bangumi.as_ref(), &rules, title));
+        let text = match result {
+            Ok((r, by)) => {
+                let by = match by {
+                    updater::ParsedBy::Rule(rule) => fill(
+                        self.t(Msg::ParsedByRule),
+                        &[&rule.id, &self.scope_text(&rule.scope), &rule.pattern],
+                    ),
+                    updater::ParsedBy::BuiltIn => self.t(Msg::ParsedByBuiltIn).to_string(),
+                    updater::ParsedBy::Learned => self.t(Msg::ParsedByLearned).to_string(),
+                };
+                let name = [&r.title_zh, &r.title_en, &r.title_jp]
+                    .into_iter()
+                    .filter(|t| !t.is_empty())
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join(" / ");
+                let version = r.version.map(|v| v.to_string()).unwrap_or("-".to_string());
+                let fields = fill(
+                    self.t(Msg::ParseFields),
+                    &[&name, &r.season, &r.episode, &version, &r.fansub],
+                );
+                format!("{}\n{}", by, fields)
+            }
+            Err(e) => e.user_message(self.locale),
+        };
+        self.bot.send_message(self.chat_id, text).await?;
+        Ok(())
+    }
+    /// Private chats store the language for the user, group chats for the
+    /// whole chat.
+    pub async fn set_locale(&self, locale: String) -> Result<()> {
+        let locale: Locale = match locale.parse() {
+            Ok(locale) => locale,
+            Err(_) => {
+                self.bot
+                    .send_message(self.chat_id, self.t(Msg::UsageLang))
+                    .await?;
+                return Ok(());
+            }
+        };
+        // only registered users get past `resolve_user` in private chats
+        let result = if self.chat_id.is_user() {
+            self.db.set_user(database::User {
+                locale: Some(locale),
+                ..self.user.clone()
+            })
+        } else {
+            match self.db.get_chat(self.chat_id.0) {
+                Ok(chat) => self.db.set_chat(database::Chat {
+                    locale: Some(locale),
+                    ..chat.unwrap_or(database::Chat {
+                        id: self.chat_id.0,
+                        notify: database::NotifyMode::default(),
+                        locale: None,
+                    })
+                }),
+                Err(e) => Err(e),
+            }
+        };
+        match result {
+            Ok(_) => {
+                self.bot
+                    .send_message(self.chat_id, locale.text(Msg::Success))
+                    .await?;
             }
             Err(e) => {
-                log::error!("database error: {:?}", e);
-                self.bot.send_message(self.chat_id, "Failed.").await?;
+                self.reply_error("database", e).await?;
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_commands() {
+        // viewers may run these, but in groups only admins change the chat
+        for cmd in [
+            Command::Notify("off".to_string()),
+            Command::Lang("zh".to_string()),
+            Command::Follow(1),
+            Command::Unfollow(1),
+        ] {
+            assert_eq!(cmd.required_role(), database::Role::Viewer);
+            assert!(cmd.changes_chat());
+        }
+        assert!(!Command::List.changes_chat());
+    }
+
+    #[test]
+    fn test_managed_bangumi() {
+        assert_eq!(
+            Command::Check("3330".to_string()).managed_bangumi(),
+            Some(3330)
+        );
+        assert_eq!(Command::Check(String::new()).managed_bangumi(), None);
+        assert_eq!(Command::Enable(1).managed_bangumi(), Some(1));
+        assert_eq!(Command::List.managed_bangumi(), None);
+    }
+
+    #[test]
+    fn test_callback_data() {
+        for callback in [
+            Callback::List,
+            Callback::Show(3330),
+            Callback::Filters(1),
+            Callback::ConfirmRemove(2353, organizer::RemoveMode::TorrentsAndData),
+            Callback::SkipGroup(3330, "喵萌奶茶屋&LoliHouse".to_string()),
+            Callback::Resolve(3330, 12, 2, 5),
+            Callback::IgnoreItem(3330, 12),
+        ] {
+            assert_eq!(Callback::parse(&callback.data()), Some(callback));
+        }
+        let long = Callback::SkipGroup(1, "字幕组".repeat(10)).data();
+        assert!(long.len() <= 64);
+        assert!(matches!(
+            Callback::parse(&long),
+            Some(Callback::SkipGroup(1, _))
+        ));
+        assert_eq!(Callback::parse("remove:abc"), None);
+        assert_eq!(Callback::parse("unknown:1"), None);
+        assert_eq!(Callback::parse("resolve:1:2:3"), None);
+    }
+}
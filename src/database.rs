@@ -1,12 +1,18 @@
-use polodb_core::{bson::doc, Database};
+use crate::error::Result;
+use crate::i18n::Locale;
+use crate::{mikan, seeding, title_parser};
+use polodb_core::{
+    bson::{doc, Document},
+    Database,
+};
 use serde::{Deserialize, Serialize};
-use std::error::Error;
+use std::sync::Mutex;
 use thiserror::Error;
 
-type BoxErr = Box<dyn Error + Send + Sync>;
-
 pub struct Client {
     db: Database,
+    /// held while allocating an id and inserting under it
+    ids: Mutex<()>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -19,6 +25,208 @@ pub struct Bangumi {
     pub rss_url: String,
     pub enabled: bool,
     pub not_contains: Vec<String>,
+    #[serde(default)]
+    pub episodes: Vec<Episode>,
+    #[serde(default)]
+    pub mode: SubscribeMode,
+    /// feed items present when subscribing in `OnlyNew` mode
+    #[serde(default)]
+    pub ignored: Vec<String>,
+    /// users allowed to manage this bangumi besides admins
+    #[serde(default)]
+    pub owners: Vec<u64>,
+    /// chats following this bangumi
+    #[serde(default)]
+    pub chats: Vec<i64>,
+    /// channel (`@username` or chat id) release announcements are posted to
+    #[serde(default)]
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub poll: PollMode,
+    /// validators and last processed item of the feed
+    #[serde(default)]
+    pub feed: mikan::FeedCache,
+    /// overrides of the global seeding policy
+    #[serde(default)]
+    pub seeding: seeding::SeedingPolicy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Viewer,
+    Member,
+    Admin,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    pub id: u64,
+    pub role: Role,
+    /// chosen with `/lang`, otherwise taken from the Telegram client
+    #[serde(default)]
+    pub locale: Option<Locale>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum NotifyMode {
+    /// every bangumi
+    All,
+    /// only bangumi followed by the chat
+    #[default]
+    Followed,
+    Off,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Chat {
+    pub id: i64,
+    pub notify: NotifyMode,
+    #[serde(default)]
+    pub locale: Option<Locale>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum SubscribeMode {
+    /// everything in the feed
+    #[default]
+    All,
+    /// episodes numbered from N on
+    FromEpisode(i16),
+    /// only items published after subscribing
+    OnlyNew,
+}
+
+/// How often the feed of a bangumi is polled.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PollMode {
+    /// often around the airing weekday, rarely otherwise
+    #[default]
+    Auto,
+    /// every N minutes
+    Every(u32),
+}
+
+impl std::str::FromStr for Role {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "admin" => Ok(Role::Admin),
+            "member" => Ok(Role::Member),
+            "viewer" => Ok(Role::Viewer),
+            _ => Err(DatabaseError::InvalidValue {
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl std::str::FromStr for NotifyMode {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "all" => Ok(NotifyMode::All),
+            "followed" => Ok(NotifyMode::Followed),
+            "off" => Ok(NotifyMode::Off),
+            _ => Err(DatabaseError::InvalidValue {
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl std::str::FromStr for SubscribeMode {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "" | "all" => Ok(SubscribeMode::All),
+            "new" => Ok(SubscribeMode::OnlyNew),
+            n => Ok(SubscribeMode::FromEpisode(n.parse()?)),
+        }
+    }
+}
+
+impl std::str::FromStr for PollMode {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "auto" => Ok(PollMode::Auto),
+            n => match n.parse::<u32>() {
+                Ok(minutes) if minutes > 0 => Ok(PollMode::Every(minutes)),
+                _ => Err(DatabaseError::InvalidValue {
+                    value: s.to_string(),
+                }),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Episode {
+    pub season: i8,
+    pub episode: i16,
+    pub hash: String,
+    pub title: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrganizeTask {
+    pub hash: String,
+    pub bangumi_id: u32,
+    pub save_dir: String,
+    pub save_name: String,
+    pub done: bool,
+    pub files: Vec<String>,
+}
+
+/// A feed item whose download failed, tried again after growing delays.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RetryTask {
+    /// torrent hash of the feed item
+    pub hash: String,
+    pub bangumi_id: u32,
+    pub title: String,
+    pub link: String,
+    pub torrent_url: String,
+    pub attempts: u32,
+    /// unix timestamp of the next attempt
+    pub next_try: i64,
+    pub last_error: String,
+    pub gave_up: bool,
+}
+
+/// A feed item whose title could not be parsed, waiting for the user to
+/// give its episode or ignore it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QuarantineItem {
+    pub id: u32,
+    pub bangumi_id: u32,
+    pub hash: String,
+    pub title: String,
+    pub link: String,
+    pub torrent_url: String,
+}
+
+/// A user regex tried before the built-in parser on titles of a fansub or
+/// of a subscription, or a rule learned from a title resolved by hand, tried
+/// after it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ParseRule {
+    pub id: u32,
+    pub scope: RuleScope,
+    /// the regex, or the shape of a learned rule
+    pub pattern: String,
+    #[serde(default)]
+    pub learned: Option<title_parser::LearnedRule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RuleScope {
+    Fansub(String),
+    Bangumi(u32),
 }
 
 #[derive(Error, Debug)]
@@ -27,36 +235,41 @@ pub enum DatabaseError {
     BangumiNotFound,
     #[error("Bangumi existed")]
     BangumiExisted,
+    #[error("Invalid value {value}")]
+    InvalidValue { value: String },
 }
 
 impl Client {
-    pub fn new(db_path: &str) -> Result<Self, BoxErr> {
+    pub fn new(db_path: &str) -> Result<Self> {
         let db = Database::open_file(db_path)?;
-        Ok(Self { db })
+        Ok(Self {
+            db,
+            ids: Mutex::new(()),
+        })
     }
-    pub fn get_bangumi(&self, id: u32) -> Result<Option<Bangumi>, BoxErr> {
+    pub fn get_bangumi(&self, id: u32) -> Result<Option<Bangumi>> {
         let bangumi = self
             .db
             .collection::<Bangumi>("bangumi")
             .find_one(doc! { "id": id })?;
         Ok(bangumi)
     }
-    pub fn insert_bangumi(&self, bangumi: Bangumi) -> Result<(), BoxErr> {
+    pub fn insert_bangumi(&self, bangumi: Bangumi) -> Result<()> {
         if self.get_bangumi(bangumi.id)?.is_some() {
-            return Err(Box::new(DatabaseError::BangumiExisted));
+            return Err(DatabaseError::BangumiExisted.into());
         }
         self.db
             .collection::<Bangumi>("bangumi")
             .insert_one(bangumi)?;
         Ok(())
     }
-    pub fn delete_bangumi(&self, id: u32) -> Result<(), BoxErr> {
+    pub fn delete_bangumi(&self, id: u32) -> Result<()> {
         self.db
             .collection::<Bangumi>("bangumi")
             .delete_one(doc! { "id": id })?;
         Ok(())
     }
-    pub fn get_bangumi_all(&self) -> Result<Vec<Bangumi>, BoxErr> {
+    pub fn get_bangumi_all(&self) -> Result<Vec<Bangumi>> {
         let bangumi = self
             .db
             .collection::<Bangumi>("bangumi")
@@ -64,7 +277,7 @@ impl Client {
             .collect::<polodb_core::Result<Vec<Bangumi>>>()?;
         Ok(bangumi)
     }
-    pub fn get_rss_bangumi(&self) -> Result<Vec<Bangumi>, BoxErr> {
+    pub fn get_rss_bangumi(&self) -> Result<Vec<Bangumi>> {
         let bangumi = self
             .db
             .collection::<Bangumi>("bangumi")
@@ -72,7 +285,7 @@ impl Client {
             .collect::<polodb_core::Result<Vec<Bangumi>>>()?;
         Ok(bangumi)
     }
-    pub fn add_downloaded(&self, id: u32, hash: &str) -> Result<(), BoxErr> {
+    pub fn add_downloaded(&self, id: u32, hash: &str) -> Result<()> {
         let mut bangumi = self
             .get_bangumi(id)?
             .ok_or(DatabaseError::BangumiNotFound)?;
@@ -85,31 +298,552 @@ impl Client {
         }
         Ok(())
     }
-    pub fn is_downloaded(&self, id: u32, hash: &str) -> Result<bool, BoxErr> {
+    pub fn add_episode(&self, id: u32, episode: Episode) -> Result<()> {
+        let mut bangumi = self
+            .get_bangumi(id)?
+            .ok_or(DatabaseError::BangumiNotFound)?;
+        if !episode.hash.is_empty() && !bangumi.downloaded.contains(&episode.hash) {
+            bangumi.downloaded.push(episode.hash.clone());
+        }
+        bangumi.episodes.retain(|e| {
+            if episode.hash.is_empty() {
+                e.hash.is_empty() || e.season != episode.season || e.episode != episode.episode
+            } else {
+                e.hash != episode.hash
+            }
+        });
+        bangumi.episodes.push(episode);
+        self.db.collection::<Bangumi>("bangumi").update_one(
+            doc! { "id": id },
+            doc! { "$set": {
+                "downloaded": bangumi.downloaded,
+                "episodes": polodb_core::bson::to_bson(&bangumi.episodes)?,
+            } },
+        )?;
+        Ok(())
+    }
+    /// Forget a downloaded episode so the next RSS update fetches it again.
+    /// The feed cache is reset too, or the item would stay seen.
+    pub fn remove_episode(&self, id: u32, episode: &Episode) -> Result<()> {
+        let mut bangumi = self
+            .get_bangumi(id)?
+            .ok_or(DatabaseError::BangumiNotFound)?;
+        bangumi.downloaded.retain(|h| h != &episode.hash);
+        bangumi.episodes.retain(|e| e != episode);
+        self.db.collection::<Bangumi>("bangumi").update_one(
+            doc! { "id": id },
+            doc! { "$set": {
+                "downloaded": bangumi.downloaded,
+                "episodes": polodb_core::bson::to_bson(&bangumi.episodes)?,
+                "feed": polodb_core::bson::to_bson(&mikan::FeedCache::default())?,
+            } },
+        )?;
+        Ok(())
+    }
+    pub fn is_downloaded(&self, id: u32, hash: &str) -> Result<bool> {
         let bangumi = self
             .get_bangumi(id)?
             .ok_or(DatabaseError::BangumiNotFound)?;
         Ok(bangumi.downloaded.contains(&hash.to_string()))
     }
-    pub fn bangumi_exists(&self, id: u32) -> Result<bool, BoxErr> {
+    pub fn bangumi_exists(&self, id: u32) -> Result<bool> {
         let bangumi = self.get_bangumi(id)?;
         Ok(bangumi.is_some())
     }
-    pub fn set_bangumi_enabled(&self, id: u32, enabled: bool) -> Result<(), BoxErr> {
-        self.db
+    /// Apply `update` to a bangumi, failing when there is none with `id`.
+    fn update_bangumi(&self, id: u32, update: Document) -> Result<()> {
+        let result = self
+            .db
             .collection::<Bangumi>("bangumi")
-            .update_one(doc! { "id": id }, doc! { "$set": { "enabled": enabled } })?;
+            .update_one(doc! { "id": id }, update)?;
+        // a bangumi already set that way may not count as modified
+        if result.modified_count == 0 && !self.bangumi_exists(id)? {
+            return Err(DatabaseError::BangumiNotFound.into());
+        }
+        Ok(())
+    }
+    pub fn set_bangumi_enabled(&self, id: u32, enabled: bool) -> Result<()> {
+        self.update_bangumi(id, doc! { "$set": { "enabled": enabled } })
+    }
+    /// Set the filters and reset the feed cache, so that items filtered
+    /// before are looked at again.
+    pub fn set_bangumi_not_contains(&self, id: u32, not_contains: Vec<String>) -> Result<()> {
+        self.update_bangumi(
+            id,
+            doc! { "$set": {
+                "not_contains": not_contains,
+                "feed": polodb_core::bson::to_bson(&mikan::FeedCache::default())?,
+            } },
+        )
+    }
+    pub fn insert_organize_task(&self, task: OrganizeTask) -> Result<()> {
+        self.db
+            .collection::<OrganizeTask>("organize")
+            .insert_one(task)?;
+        Ok(())
+    }
+    pub fn get_pending_organize_tasks(&self) -> Result<Vec<OrganizeTask>> {
+        let tasks = self
+            .db
+            .collection::<OrganizeTask>("organize")
+            .find(doc! { "done": false })?
+            .collect::<polodb_core::Result<Vec<OrganizeTask>>>()?;
+        Ok(tasks)
+    }
+    pub fn get_organize_tasks(&self, bangumi_id: u32) -> Result<Vec<OrganizeTask>> {
+        let tasks = self
+            .db
+            .collection::<OrganizeTask>("organize")
+            .find(doc! { "bangumi_id": bangumi_id })?
+            .collect::<polodb_core::Result<Vec<OrganizeTask>>>()?;
+        Ok(tasks)
+    }
+    pub fn set_organize_task_done(&self, hash: &str, files: Vec<String>) -> Result<()> {
+        self.db.collection::<OrganizeTask>("organize").update_one(
+            doc! { "hash": hash },
+            doc! { "$set": { "done": true, "files": files } },
+        )?;
+        Ok(())
+    }
+    pub fn delete_organize_task(&self, hash: &str) -> Result<()> {
+        self.db
+            .collection::<OrganizeTask>("organize")
+            .delete_one(doc! { "hash": hash })?;
+        Ok(())
+    }
+    pub fn delete_organize_tasks(&self, bangumi_id: u32) -> Result<()> {
+        self.db
+            .collection::<OrganizeTask>("organize")
+            .delete_many(doc! { "bangumi_id": bangumi_id })?;
+        Ok(())
+    }
+    /// Retry tasks of a bangumi, or of all of them.
+    pub fn get_retry_tasks(&self, bangumi_id: Option<u32>) -> Result<Vec<RetryTask>> {
+        let filter = bangumi_id.map(|id| doc! { "bangumi_id": id });
+        let tasks = self
+            .db
+            .collection::<RetryTask>("retry")
+            .find(filter)?
+            .collect::<polodb_core::Result<Vec<RetryTask>>>()?;
+        Ok(tasks)
+    }
+    pub fn save_retry_task(&self, task: &RetryTask) -> Result<()> {
+        let retry = self.db.collection::<RetryTask>("retry");
+        retry.delete_one(doc! { "hash": &task.hash })?;
+        retry.insert_one(task)?;
+        Ok(())
+    }
+    pub fn delete_retry_task(&self, hash: &str) -> Result<()> {
+        self.db
+            .collection::<RetryTask>("retry")
+            .delete_one(doc! { "hash": hash })?;
         Ok(())
     }
-    pub fn set_bangumi_not_contains(
+    pub fn delete_retry_tasks(&self, bangumi_id: u32) -> Result<()> {
+        self.db
+            .collection::<RetryTask>("retry")
+            .delete_many(doc! { "bangumi_id": bangumi_id })?;
+        Ok(())
+    }
+    /// Make the failed downloads of a bangumi due now with a fresh attempt
+    /// count, returning how many there are.
+    pub fn reset_retry_tasks(&self, bangumi_id: u32) -> Result<usize> {
+        let tasks = self.get_retry_tasks(Some(bangumi_id))?;
+        self.db.collection::<RetryTask>("retry").update_many(
+            doc! { "bangumi_id": bangumi_id },
+            doc! { "$set": { "attempts": 0, "next_try": 0_i64, "gave_up": false } },
+        )?;
+        Ok(tasks.len())
+    }
+    pub fn get_quarantine(&self, bangumi_id: Option<u32>) -> Result<Vec<QuarantineItem>> {
+        let filter = bangumi_id.map(|id| doc! { "bangumi_id": id });
+        let items = self
+            .db
+            .collection::<QuarantineItem>("quarantine")
+            .find(filter)?
+            .collect::<polodb_core::Result<Vec<QuarantineItem>>>()?;
+        Ok(items)
+    }
+    pub fn get_quarantine_item(&self, id: u32) -> Result<Option<QuarantineItem>> {
+        let item = self
+            .db
+            .collection::<QuarantineItem>("quarantine")
+            .find_one(doc! { "id": id })?;
+        Ok(item)
+    }
+    /// Store an item under the next free id, which is filled in.
+    pub fn insert_quarantine_item(&self, mut item: QuarantineItem) -> Result<QuarantineItem> {
+        let _ids = self.ids.lock().unwrap_or_else(|e| e.into_inner());
+        item.id = self
+            .get_quarantine(None)?
+            .iter()
+            .map(|i| i.id)
+            .max()
+            .unwrap_or_default()
+            + 1;
+        self.db
+            .collection::<QuarantineItem>("quarantine")
+            .insert_one(&item)?;
+        Ok(item)
+    }
+    pub fn delete_quarantine_item(&self, id: u32) -> Result<()> {
+        self.db
+            .collection::<QuarantineItem>("quarantine")
+            .delete_one(doc! { "id": id })?;
+        Ok(())
+    }
+    /// Drop a quarantined item and remember to skip it when the feed is
+    /// looked at again.
+    pub fn ignore_quarantine_item(&self, item: &QuarantineItem) -> Result<()> {
+        if let Some(mut bangumi) = self.get_bangumi(item.bangumi_id)? {
+            if !bangumi.ignored.contains(&item.hash) {
+                bangumi.ignored.push(item.hash.clone());
+                self.db.collection::<Bangumi>("bangumi").update_one(
+                    doc! { "id": item.bangumi_id },
+                    doc! { "$set": { "ignored": bangumi.ignored } },
+                )?;
+            }
+        }
+        self.delete_quarantine_item(item.id)
+    }
+    pub fn delete_quarantine(&self, bangumi_id: u32) -> Result<()> {
+        self.db
+            .collection::<QuarantineItem>("quarantine")
+            .delete_many(doc! { "bangumi_id": bangumi_id })?;
+        Ok(())
+    }
+    pub fn get_parse_rules(&self) -> Result<Vec<ParseRule>> {
+        let rules = self
+            .db
+            .collection::<ParseRule>("parse_rules")
+            .find(None)?
+            .collect::<polodb_core::Result<Vec<ParseRule>>>()?;
+        Ok(rules)
+    }
+    /// Store a rule under the next free id, which is filled in.
+    pub fn insert_parse_rule(&self, mut rule: ParseRule) -> Result<ParseRule> {
+        let _ids = self.ids.lock().unwrap_or_else(|e| e.into_inner());
+        rule.id = self
+            .get_parse_rules()?
+            .iter()
+            .map(|r| r.id)
+            .max()
+            .unwrap_or_default()
+            + 1;
+        self.db
+            .collection::<ParseRule>("parse_rules")
+            .insert_one(&rule)?;
+        Ok(rule)
+    }
+    pub fn delete_parse_rule(&self, id: u32) -> Result<bool> {
+        let result = self
+            .db
+            .collection::<ParseRule>("parse_rules")
+            .delete_one(doc! { "id": id })?;
+        Ok(result.deleted_count > 0)
+    }
+    /// Store a learned rule for its fansub, or for the bangumi when the
+    /// title has none, replacing the one learned for titles of the same shape.
+    pub fn learn_parse_rule(
         &self,
-        id: u32,
-        not_contains: Vec<String>,
-    ) -> Result<(), BoxErr> {
+        bangumi_id: u32,
+        rule: title_parser::LearnedRule,
+    ) -> Result<ParseRule> {
+        let scope = match rule.fansub.is_empty() {
+            true => RuleScope::Bangumi(bangumi_id),
+            false => RuleScope::Fansub(rule.fansub.clone()),
+        };
+        for old in self.get_parse_rules()? {
+            if old.scope == scope && old.learned.as_ref().is_some_and(|r| r.shape == rule.shape) {
+                self.delete_parse_rule(old.id)?;
+            }
+        }
+        self.insert_parse_rule(ParseRule {
+            id: 0,
+            scope,
+            pattern: rule.shape.clone(),
+            learned: Some(rule),
+        })
+    }
+    pub fn set_bangumi_seeding(&self, id: u32, policy: &seeding::SeedingPolicy) -> Result<()> {
+        self.update_bangumi(
+            id,
+            doc! { "$set": { "seeding": polodb_core::bson::to_bson(policy)? } },
+        )
+    }
+    pub fn set_bangumi_channel(&self, id: u32, channel: Option<String>) -> Result<()> {
+        self.update_bangumi(id, doc! { "$set": { "channel": channel } })
+    }
+    pub fn set_bangumi_poll(&self, id: u32, poll: PollMode) -> Result<()> {
+        self.update_bangumi(
+            id,
+            doc! { "$set": { "poll": polodb_core::bson::to_bson(&poll)? } },
+        )
+    }
+    pub fn set_bangumi_feed(&self, id: u32, feed: &mikan::FeedCache) -> Result<()> {
+        self.update_bangumi(
+            id,
+            doc! { "$set": { "feed": polodb_core::bson::to_bson(feed)? } },
+        )
+    }
+    pub fn follow_bangumi(&self, id: u32, chat_id: i64, follow: bool) -> Result<()> {
+        let mut bangumi = self
+            .get_bangumi(id)?
+            .ok_or(DatabaseError::BangumiNotFound)?;
+        bangumi.chats.retain(|c| *c != chat_id);
+        if follow {
+            bangumi.chats.push(chat_id);
+        }
         self.db.collection::<Bangumi>("bangumi").update_one(
             doc! { "id": id },
-            doc! { "$set": { "not_contains": not_contains } },
+            doc! { "$set": { "chats": bangumi.chats } },
         )?;
         Ok(())
     }
+    pub fn get_user(&self, id: u64) -> Result<Option<User>> {
+        let user = self
+            .db
+            .collection::<User>("users")
+            .find_one(doc! { "id": id as i64 })?;
+        Ok(user)
+    }
+    pub fn get_users(&self) -> Result<Vec<User>> {
+        let users = self
+            .db
+            .collection::<User>("users")
+            .find(None)?
+            .collect::<polodb_core::Result<Vec<User>>>()?;
+        Ok(users)
+    }
+    pub fn set_user(&self, user: User) -> Result<()> {
+        self.delete_user(user.id)?;
+        self.db.collection::<User>("users").insert_one(user)?;
+        Ok(())
+    }
+    pub fn delete_user(&self, id: u64) -> Result<()> {
+        self.db
+            .collection::<User>("users")
+            .delete_many(doc! { "id": id as i64 })?;
+        Ok(())
+    }
+    pub fn get_chat(&self, id: i64) -> Result<Option<Chat>> {
+        let chat = self
+            .db
+            .collection::<Chat>("chats")
+            .find_one(doc! { "id": id })?;
+        Ok(chat)
+    }
+    pub fn set_chat(&self, chat: Chat) -> Result<()> {
+        self.db
+            .collection::<Chat>("chats")
+            .delete_many(doc! { "id": chat.id })?;
+        self.db.collection::<Chat>("chats").insert_one(chat)?;
+        Ok(())
+    }
+    /// Locale for messages sent to a chat: the one set for the chat, or for
+    /// the user in a private chat.
+    pub fn chat_locale(&self, id: i64) -> Result<Option<Locale>> {
+        if let Some(locale) = self.get_chat(id)?.and_then(|c| c.locale) {
+            return Ok(Some(locale));
+        }
+        if id > 0 {
+            return Ok(self.get_user(id as u64)?.and_then(|u| u.locale));
+        }
+        Ok(None)
+    }
+    /// Chats that should be notified about a bangumi.
+    pub fn notify_chats(&self, bangumi: &Bangumi) -> Result<Vec<i64>> {
+        let chats = self
+            .db
+            .collection::<Chat>("chats")
+            .find(None)?
+            .collect::<polodb_core::Result<Vec<Chat>>>()?;
+        let mut ids: Vec<i64> = chats
+            .iter()
+            .filter(|c| c.notify == NotifyMode::All)
+            .map(|c| c.id)
+            .collect();
+        for id in bangumi.chats.iter() {
+            let notify = chats
+                .iter()
+                .find(|c| c.id == *id)
+                .map(|c| c.notify)
+                .unwrap_or_default();
+            if notify == NotifyMode::Followed && !ids.contains(id) {
+                ids.push(*id);
+            }
+        }
+        Ok(ids)
+    }
+    /// Make sure the bootstrap users from the environment are admins that
+    /// receive every notification, like before users were stored.
+    pub fn seed_admin(&self, id: u64) -> Result<()> {
+        if self.get_user(id)?.is_none() {
+            self.set_user(User {
+                id,
+                role: Role::Admin,
+                locale: None,
+            })?;
+        }
+        if self.get_chat(id as i64)?.is_none() {
+            self.set_chat(Chat {
+                id: id as i64,
+                notify: NotifyMode::All,
+                locale: None,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify_chats() -> Result<()> {
+        let path = std::env::temp_dir().join("otto_database_test.db");
+        std::fs::remove_file(&path).ok();
+        let db = Client::new(path.to_str().unwrap())?;
+        db.seed_admin(1)?;
+        db.set_user(User {
+            id: 2,
+            role: Role::Member,
+            locale: None,
+        })?;
+        db.set_chat(Chat {
+            id: 3,
+            notify: NotifyMode::Off,
+            locale: None,
+        })?;
+        assert_eq!(db.get_user(1)?.map(|u| u.role), Some(Role::Admin));
+        assert!(Role::Admin > Role::Member && Role::Member > Role::Viewer);
+        let bangumi = Bangumi {
+            id: 3330,
+            title: "狼与香辛料 行商邂逅贤狼".to_string(),
+            weekday: 1,
+            poster_url: String::new(),
+            downloaded: vec![],
+            rss_url: String::new(),
+            enabled: true,
+            not_contains: vec![],
+            episodes: vec![],
+            mode: SubscribeMode::All,
+            ignored: vec![],
+            owners: vec![2],
+            chats: vec![2, 3],
+            channel: None,
+            poll: PollMode::Auto,
+            feed: Default::default(),
+            seeding: Default::default(),
+        };
+        assert_eq!(db.notify_chats(&bangumi)?, vec![1, 2]);
+        std::fs::remove_file(&path).ok();
+        Ok(())
+    }
+
+    #[test]
+    fn test_learn_parse_rule() -> Result<()> {
+        let path = std::env::temp_dir().join("otto_database_rule_test.db");
+        std::fs::remove_file(&path).ok();
+        let db = Client::new(path.to_str().unwrap())?;
+        let title = "[Nekomoe kissaten] Kusuriya no Hitorigoto 2 Dai 05 Wa [WebRip 1080p]";
+        let rule = title_parser::LearnedRule::learn(title, 2, 5).unwrap();
+        db.learn_parse_rule(1, rule.clone())?;
+        // learned again from another bangumi of the fansub
+        let learned = db.learn_parse_rule(2, rule)?;
+        assert_eq!(
+            learned.scope,
+            RuleScope::Fansub("Nekomoe kissaten".to_string())
+        );
+        let rules = db.get_parse_rules()?;
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].id, learned.id);
+        let user = db.insert_parse_rule(ParseRule {
+            id: 0,
+            scope: RuleScope::Bangumi(1),
+            pattern: r"(?P<episode>\d+)".to_string(),
+            learned: None,
+        })?;
+        assert_ne!(user.id, learned.id);
+        std::fs::remove_file(&path).ok();
+        Ok(())
+    }
+
+    #[test]
+    fn test_requeue_resets_feed() -> Result<()> {
+        let path = std::env::temp_dir().join("otto_database_requeue_test.db");
+        std::fs::remove_file(&path).ok();
+        let db = Client::new(path.to_str().unwrap())?;
+        let item = |n: u8| mikan::RssEpisode {
+            title: format!("[ANi] Frieren - {:02}", n),
+            link: String::new(),
+            description: String::new(),
+            torrent_url: String::new(),
+            torrent_hash: format!("hash{}", n),
+            guid: format!("guid{}", n),
+            pub_date: None,
+        };
+        // newest first, like the feed
+        let items = vec![item(2), item(1)];
+        let episode = Episode {
+            season: 1,
+            episode: 1,
+            hash: "hash1".to_string(),
+            title: items[1].title.clone(),
+        };
+        let mut feed = mikan::FeedCache {
+            content_hash: Some("123".to_string()),
+            ..Default::default()
+        };
+        feed.mark_seen(&items);
+        db.insert_bangumi(Bangumi {
+            id: 1,
+            title: "Frieren".to_string(),
+            weekday: 5,
+            poster_url: String::new(),
+            downloaded: vec!["hash1".to_string(), "hash2".to_string()],
+            rss_url: String::new(),
+            enabled: true,
+            not_contains: vec![],
+            episodes: vec![episode.clone()],
+            mode: SubscribeMode::All,
+            ignored: vec![],
+            owners: vec![],
+            chats: vec![],
+            channel: None,
+            poll: PollMode::Auto,
+            feed,
+            seeding: Default::default(),
+        })?;
+        let b = db.get_bangumi(1)?.unwrap();
+        assert_eq!(b.feed.seen_from(&items), 0);
+
+        db.remove_episode(1, &episode)?;
+        let b = db.get_bangumi(1)?.unwrap();
+        // the next update fetches the whole feed and only hash1 is new again
+        assert_eq!(b.feed, mikan::FeedCache::default());
+        assert_eq!(b.feed.seen_from(&items), items.len());
+        assert_eq!(b.downloaded, vec!["hash2".to_string()]);
+        assert!(b.episodes.is_empty());
+
+        let mut feed = b.feed.clone();
+        feed.mark_seen(&items);
+        db.set_bangumi_feed(1, &feed)?;
+        db.set_bangumi_not_contains(1, vec![])?;
+        assert_eq!(
+            db.get_bangumi(1)?.unwrap().feed,
+            mikan::FeedCache::default()
+        );
+        // setting the same value again is no error, an unknown id is
+        db.set_bangumi_enabled(1, true)?;
+        assert!(matches!(
+            db.set_bangumi_enabled(2, true),
+            Err(crate::error::Error::Database(
+                DatabaseError::BangumiNotFound
+            ))
+        ));
+        std::fs::remove_file(&path).ok();
+        Ok(())
+    }
 }
@@ -1,17 +1,28 @@
-use qbit_api_rs::{self, types::torrents::FilesResponseItem};
+use qbit_api_rs::{
+    self,
+    error::ClientError,
+    types::torrents::{FilesResponseItem, InfoQuery, InfoResponseItem, RatioLimit},
+};
 use thiserror::Error;
+use crate::error::Result;
 use crate::utils::{file_extension, file_stem};
 
-type BoxErr = Box<dyn std::error::Error + Send + Sync>;
-
 #[derive(Error, Debug)]
 pub enum DownloaderError {
-    #[error("Failed to initialize downloader")]
-    InitError,
+    #[error("Failed to initialize downloader: {reason}")]
+    InitError { reason: String },
+    #[error("Downloader unreachable: {reason}")]
+    Unreachable { reason: String },
+    #[error("Downloader login failed")]
+    LoginFailed,
     #[error("No file to rename")]
     NoFileToRename,
     #[error("Failed to parse file name {name}")]
     FileNameError { name: String },
+    #[error("Invalid torrent url {url}")]
+    InvalidTorrentUrl { url: String },
+    #[error("Timed out waiting for metadata of {hash}")]
+    MetadataTimeout { hash: String },
 }
 
 pub struct QbitDownloader {
@@ -19,48 +30,75 @@ pub struct QbitDownloader {
 }
 
 impl QbitDownloader {
-    pub async fn new() -> Result<Self, BoxErr> {
-        let client = qbit_api_rs::client::QbitClient::new_from_env()?;
-        client.auth_login().await?;
+    pub async fn new() -> Result<Self> {
+        let client = qbit_api_rs::client::QbitClient::new_from_env().map_err(|e| match e {
+            ClientError::Initialize(reason) => DownloaderError::InitError { reason },
+            e => DownloaderError::InitError {
+                reason: e.to_string(),
+            },
+        })?;
+        client.auth_login().await.map_err(|e| match e {
+            ClientError::ReqwestError(e) => DownloaderError::Unreachable {
+                reason: e.to_string(),
+            },
+            _ => DownloaderError::LoginFailed,
+        })?;
         Ok(QbitDownloader { client })
     }
-    pub async fn download(&self, urls: Vec<String>) -> Result<(), BoxErr> {
+    pub async fn download(&self, urls: Vec<String>) -> Result<()> {
         self.client.torrents_add_by_url(&urls).await?;
         Ok(())
     }
     pub async fn download_by_torrent_file(
         &self,
         file_path: &str,
-    ) -> Result<(), BoxErr> {
+    ) -> Result<()> {
         self.client.torrents_add_by_file(&[file_path]).await?;
         Ok(())
     }
     pub fn magnet_to_hash(magnet: &str) -> String {
         let hash = magnet.trim().replace("magnet:?xt=urn:btih:", "");
-        hash.split('&').collect::<Vec<&str>>()[0].to_string()
+        hash.split('&').collect::<Vec<&str>>()[0].to_lowercase()
+    }
+    /// Wait until the downloader knows the file list of a torrent, which for
+    /// magnet links only happens once the metadata has been fetched.
+    pub async fn wait_for_metadata(&self, hash: &str, timeout_secs: u64) -> Result<()> {
+        let start = std::time::Instant::now();
+        loop {
+            if let Ok(files) = self.torrent_files(hash).await {
+                if !files.is_empty() {
+                    return Ok(());
+                }
+            }
+            if start.elapsed().as_secs() >= timeout_secs {
+                return Err(DownloaderError::MetadataTimeout {
+                    hash: hash.to_string(),
+                }
+                .into());
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+        }
     }
     pub async fn torrent_files(
         &self,
         hash: &str,
-    ) -> Result<Vec<FilesResponseItem>, BoxErr> {
+    ) -> Result<Vec<FilesResponseItem>> {
         let files = self.client.torrents_files(hash, None).await?;
         Ok(files)
     }
-    pub async fn move_files(
-        &self,
-        hash: &str,
-        save_dir: &str,
+    /// Pick the main video file and its sidecars (files sharing its stem),
+    /// paired with the name each should get under `save_name`.
+    pub fn rename_plan(
+        files: &[FilesResponseItem],
         save_name: &str,
-    ) -> Result<(), BoxErr> {
-        self.client.torrents_set_location(&[hash], save_dir).await?;
-        let files = self.torrent_files(&hash).await?;
+    ) -> Result<Vec<(String, String)>> {
         let mut to_rename: Vec<String> = Vec::new();
         if files.len() == 1 {
             to_rename.push(files[0].name.clone());
         } else {
             let file = match files.iter().max_by_key(|f| f.size) {
                 Some(f) => f,
-                None => return Err(Box::new(DownloaderError::NoFileToRename)),
+                None => return Err(DownloaderError::NoFileToRename.into()),
             };
             let name = file_stem(&file.name).ok_or(DownloaderError::FileNameError {
                 name: file.name.clone(),
@@ -72,38 +110,125 @@ impl QbitDownloader {
                 }
             }
         }
-        for f in to_rename.iter() {
-            let ext = file_extension(f).ok_or(DownloaderError::FileNameError {
+        let mut plan = Vec::new();
+        for f in to_rename.into_iter() {
+            let ext = file_extension(&f).ok_or(DownloaderError::FileNameError {
                 name: f.clone(),
             })?;
             let new_name = format!("{}.{}", save_name, ext);
-            self.client.torrents_rename_file(hash, f, &new_name).await?;
+            plan.push((f, new_name));
+        }
+        Ok(plan)
+    }
+    pub async fn move_files(
+        &self,
+        hash: &str,
+        save_dir: &str,
+        save_name: &str,
+    ) -> Result<()> {
+        self.client.torrents_set_location(&[hash], save_dir).await?;
+        let files = self.torrent_files(hash).await?;
+        for (f, new_name) in Self::rename_plan(&files, save_name)?.iter() {
+            self.client.torrents_rename_file(hash, f, new_name).await?;
         }
         self.client.torernts_rename(hash, save_name).await?;
         Ok(())
     }
+    /// Remove torrents from qBittorrent, with their downloaded data or not.
+    pub async fn delete(&self, hashes: &[String], delete_files: bool) -> Result<()> {
+        self.client.torrents_delete(hashes, delete_files).await?;
+        Ok(())
+    }
+    pub async fn torrents_info(&self, hashes: &[String]) -> Result<Vec<InfoResponseItem>> {
+        let query = InfoQuery {
+            hashes: Some(hashes.to_vec()),
+            ..Default::default()
+        };
+        Ok(self.client.torrents_info(&query).await?)
+    }
+    /// Set the share and upload limits of torrents, `None` leaving a limit
+    /// as it is. Ratio and seeding time are set together, an unset one of
+    /// them follows the global qBittorrent limit.
+    pub async fn set_seeding_limits(
+        &self,
+        hashes: &[String],
+        ratio: Option<f64>,
+        minutes: Option<i64>,
+        upload_kib: Option<u64>,
+    ) -> Result<()> {
+        if ratio.is_some() || minutes.is_some() {
+            let ratio_limit = match ratio {
+                Some(ratio_limit) => RatioLimit::Limit { ratio_limit },
+                None => RatioLimit::default(),
+            };
+            self.client
+                .torrents_set_share_limits(hashes, ratio_limit, minutes.unwrap_or(-2))
+                .await?;
+        }
+        if let Some(kib) = upload_kib {
+            self.client
+                .torrents_set_upload_limit(hashes, kib.saturating_mul(1024))
+                .await?;
+        }
+        Ok(())
+    }
+    pub async fn torrent_info(&self, hash: &str) -> Result<Option<InfoResponseItem>> {
+        let query = InfoQuery {
+            hashes: Some(vec![hash.to_string()]),
+            ..Default::default()
+        };
+        let info = self.client.torrents_info(&query).await?;
+        Ok(info.into_iter().next())
+    }
+    /// Seconds a torrent has seeded, without the time it was stopped.
+    pub async fn seeding_time(&self, hash: &str) -> Result<i64> {
+        let properties = self.client.torrents_properties(hash).await?;
+        Ok(properties.seeding_time)
+    }
     pub async fn download_to(
         &self,
         url: &str,
         save_dir: &str,
         save_name: &str,
-    ) -> Result<(), BoxErr> {
+    ) -> Result<()> {
         let hash = QbitDownloader::magnet_to_hash(url);
         self.client.torrents_add_by_url(&[url.to_string()]).await?;
+        self.wait_for_metadata(&hash, 120).await?;
         self.move_files(&hash, save_dir, save_name).await?;
         Ok(())
     }
+    /// Add a magnet link into `save_dir` without renaming anything inside it.
+    pub async fn download_into(&self, url: &str, save_dir: &str) -> Result<()> {
+        let hash = QbitDownloader::magnet_to_hash(url);
+        self.client.torrents_add_by_url(&[url.to_string()]).await?;
+        self.wait_for_metadata(&hash, 120).await?;
+        self.client.torrents_set_location(&[&hash], save_dir).await?;
+        Ok(())
+    }
     pub async fn download_by_torrent_to(
         &self,
         file_path: &str,
         hash: &str,
         save_dir: &str,
         save_name: &str,
-    ) -> Result<(), BoxErr> {
+    ) -> Result<()> {
         self.client.torrents_add_by_file(&[file_path]).await?;
         // wait for the torrent to be added
         tokio::time::sleep(std::time::Duration::from_secs(3)).await;
         self.move_files(hash, save_dir, save_name).await?;
         Ok(())
     }
+    /// Add a torrent into `save_dir` without renaming anything inside it.
+    pub async fn download_by_torrent_into(
+        &self,
+        file_path: &str,
+        hash: &str,
+        save_dir: &str,
+    ) -> Result<()> {
+        self.client.torrents_add_by_file(&[file_path]).await?;
+        // wait for the torrent to be added
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+        self.client.torrents_set_location(&[hash], save_dir).await?;
+        Ok(())
+    }
 }
@@ -0,0 +1,108 @@
+use crate::database::DatabaseError;
+use crate::downloader::DownloaderError;
+use crate::i18n::{fill, Locale, Msg};
+use crate::mikan::MikanError;
+use crate::organizer::OrganizeError;
+use crate::seeding::SeedingError;
+use crate::title_parser::ParseError;
+use crate::updater::UpdateError;
+use thiserror::Error;
+
+/// Errors of the library modules, each of which the bot can explain to the
+/// user together with what to do about it.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+    #[error("Storage error: {0}")]
+    Storage(#[from] polodb_core::Error),
+    #[error("Serialize error: {0}")]
+    Serialize(#[from] polodb_core::bson::ser::Error),
+    #[error(transparent)]
+    Mikan(#[from] MikanError),
+    #[error("Request error: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("RSS error: {0}")]
+    Rss(#[from] rss::Error),
+    #[error(transparent)]
+    Downloader(#[from] DownloaderError),
+    #[error("qBittorrent error: {0}")]
+    Qbit(#[from] qbit_api_rs::error::ClientError),
+    #[error("Torrent error: {0}")]
+    Torrent(#[from] lava_torrent::LavaTorrentError),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    Organize(#[from] OrganizeError),
+    #[error(transparent)]
+    Update(#[from] UpdateError),
+    #[error(transparent)]
+    Seeding(#[from] SeedingError),
+    #[error("Telegram error: {0}")]
+    Telegram(#[from] teloxide::RequestError),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// What went wrong and how to fix it, in the user's language.
+    pub fn user_message(&self, locale: Locale) -> String {
+        let t = |msg| locale.text(msg);
+        match self {
+            Error::Database(DatabaseError::BangumiNotFound) => t(Msg::ErrBangumiNotFound).into(),
+            Error::Database(DatabaseError::BangumiExisted) => t(Msg::BangumiExists).into(),
+            Error::Database(DatabaseError::InvalidValue { value }) => {
+                fill(t(Msg::ErrInvalidValue), &[value])
+            }
+            Error::Storage(e) => fill(t(Msg::ErrStorage), &[e]),
+            Error::Serialize(e) => fill(t(Msg::ErrStorage), &[e]),
+            Error::Mikan(MikanError::InvalidRssUrl { url }) => {
+                fill(t(Msg::ErrInvalidRssUrl), &[url])
+            }
+            Error::Mikan(e) => fill(t(Msg::ErrMikanPage), &[e]),
+            Error::Request(e) => fill(t(Msg::ErrRequest), &[e]),
+            Error::Telegram(e) => fill(t(Msg::ErrRequest), &[e]),
+            Error::Rss(e) => fill(t(Msg::ErrRss), &[e]),
+            Error::Downloader(DownloaderError::InitError { reason }) => {
+                fill(t(Msg::ErrQbitConfig), &[reason])
+            }
+            Error::Downloader(DownloaderError::Unreachable { reason }) => {
+                fill(t(Msg::ErrQbitUnreachable), &[reason])
+            }
+            Error::Downloader(DownloaderError::LoginFailed) => t(Msg::ErrQbitLogin).into(),
+            Error::Downloader(DownloaderError::MetadataTimeout { .. }) => {
+                t(Msg::ErrMetadataTimeout).into()
+            }
+            Error::Downloader(e) => fill(t(Msg::ErrDownload), &[e]),
+            Error::Qbit(e) => fill(t(Msg::ErrQbit), &[e]),
+            Error::Torrent(e) => fill(t(Msg::ErrTorrent), &[e]),
+            Error::Parse(e) => fill(t(Msg::ErrParse), &[e]),
+            Error::Update(UpdateError::AlreadyRunning) => t(Msg::ErrCheckRunning).into(),
+            Error::Update(UpdateError::Rejected { issue }) => issue.message(locale),
+            Error::Seeding(SeedingError::InvalidPolicy { value }) => {
+                fill(t(Msg::ErrInvalidValue), &[value])
+            }
+            Error::Organize(e) => fill(t(Msg::ErrIo), &[e]),
+            Error::Io(e) => fill(t(Msg::ErrIo), &[e]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_message() {
+        let e = Error::from(MikanError::InvalidRssUrl {
+            url: "https://mikanani.me/RSS/MyBangumi".to_string(),
+        });
+        assert!(e
+            .user_message(Locale::En)
+            .contains("https://mikanani.me/RSS/MyBangumi"));
+        let e = Error::from(DownloaderError::LoginFailed);
+        assert!(e.user_message(Locale::ZhCn).contains("QBIT_PASSWORD"));
+    }
+}
@@ -0,0 +1,386 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    ZhCn,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::En, Locale::ZhCn];
+
+    /// Map a Telegram `language_code` to a supported locale.
+    pub fn from_language_code(code: Option<&str>) -> Option<Self> {
+        let code = code?.to_lowercase();
+        if code.starts_with("zh") {
+            Some(Locale::ZhCn)
+        } else if code.starts_with("en") {
+            Some(Locale::En)
+        } else {
+            None
+        }
+    }
+    pub fn language_code(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::ZhCn => "zh",
+        }
+    }
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "" | "en" | "english" => Ok(Locale::En),
+            "zh" | "zh-cn" | "zh_cn" | "cn" | "中文" => Ok(Locale::ZhCn),
+            _ => Err(format!("unknown locale {}", s)),
+        }
+    }
+}
+
+/// Replace each `{}` in `template` with the next argument.
+pub fn fill(template: &str, args: &[&(dyn Display + Sync)]) -> String {
+    let mut result = String::new();
+    let mut args = args.iter();
+    let mut parts = template.split("{}").peekable();
+    while let Some(part) = parts.next() {
+        result.push_str(part);
+        if parts.peek().is_some() {
+            match args.next() {
+                Some(arg) => result.push_str(&arg.to_string()),
+                None => result.push_str("{}"),
+            }
+        }
+    }
+    result
+}
+
+macro_rules! messages {
+    ($($key:ident => ($en:expr, $zh:expr),)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Msg {
+            $($key,)*
+        }
+
+        impl Locale {
+            pub fn text(&self, msg: Msg) -> &'static str {
+                match (self, msg) {
+                    $(
+                        (Locale::En, Msg::$key) => $en,
+                        (Locale::ZhCn, Msg::$key) => $zh,
+                    )*
+                }
+            }
+        }
+    };
+}
+
+messages! {
+    Success => ("Success.", "成功。"),
+    Fetching => ("fetching...", "获取中..."),
+    PermissionDenied => ("Permission denied.", "没有权限。"),
+    GroupAdminOnly => ("Only group admins can do this.", "只有群管理员可以执行此操作。"),
+    NoBangumi => ("no bangumi found.", "没有番剧。"),
+    BangumiNotFound => ("Bangumi not found.", "未找到番剧。"),
+    BangumiExists => ("Bangumi already exists.", "番剧已存在。"),
+    RssUrlRequired => ("rss url required.", "需要 RSS 链接。"),
+    InvalidMode => (
+        "mode should be all, new or an episode number.",
+        "模式应为 all、new 或集数。"
+    ),
+    EpisodeUrlRequired => ("mikan episode url required.", "需要蜜柑计划的剧集链接。"),
+    CannotParseTitle => ("cannot parse title: {}", "无法解析标题：{}"),
+    EpisodeDownloaded => ("Episode already downloaded.", "该集已下载。"),
+    EpisodeQueued => ("{} queued.", "{} 已加入下载。"),
+    NothingToBackfill => ("nothing to backfill.", "没有可补全的剧集。"),
+    BackfillQueued => ("queued:\n{}", "已加入下载：\n{}"),
+    Removed => ("{} removed.", "{} 已删除。"),
+    Paused => ("Paused.", "已暂停。"),
+    GroupSkipped => ("{} will be skipped.", "将跳过 {}。"),
+    ConfirmRemove => (
+        "Remove {}? Choose what happens to its {} torrents and the files.",
+        "确定删除 {}？请选择如何处理它的 {} 个种子和文件。"
+    ),
+    FiltersHint => (
+        "{}\nnot contains: {}\n\nUse /nc {} <word1,word2,...> to change.",
+        "{}\n排除关键词：{}\n\n使用 /nc {} <词1,词2,...> 修改。"
+    ),
+    Card => (
+        "{}\n{}\nenabled: {}\ndownloaded: {}",
+        "{}\n{}\n启用：{}\n已下载：{}"
+    ),
+    Info => (
+        "id: {}\ntitle: {}\nweekday: {}\nposter: {}\nurl: {}\nenabled: {}\nmode: {}\nnot contains: {}\ndownloaded: {}\npoll: {}",
+        "ID：{}\n标题：{}\n放送星期：{}\n海报：{}\n链接：{}\n启用：{}\n模式：{}\n排除关键词：{}\n已下载：{}\n轮询：{}"
+    ),
+    ScanSummary => (
+        "missing: {}\nextra: {}\nunknown: {}\nuntracked: {}",
+        "缺失：{}\n多余：{}\n未知：{}\n无记录：{}"
+    ),
+    ScanBackfilled => (
+        "added episode records for {} earlier downloads.",
+        "已为 {} 个早期下载补充剧集记录。"
+    ),
+    ScanUntracked => ("untracked (no episode record)", "无记录（没有剧集记录）"),
+    ScanMissing => ("missing", "缺失"),
+    ScanExtra => ("extra", "多余"),
+    ScanUnknown => ("unknown", "未知"),
+    AndMore => ("... and {} more", "... 以及另外 {} 项"),
+    Requeued => ("{} episodes requeued.", "已重新排队 {} 集。"),
+    RequeueFailed => ("Requeue failed.", "重新排队失败。"),
+    OwnRole => ("Cannot change your own role.", "不能修改自己的角色。"),
+    UsageBackfill => ("Usage: /backfill <id> <from>-<to>", "用法：/backfill <id> <起始>-<结束>"),
+    UsageNotify => ("Usage: /notify <all|followed|off>", "用法：/notify <all|followed|off>"),
+    UsageUser => (
+        "Usage: /user <user_id> <admin|member|viewer|remove>",
+        "用法：/user <用户ID> <admin|member|viewer|remove>"
+    ),
+    UsageChannel => (
+        "Usage: /channel <id> <@channel|chat_id|none>",
+        "用法：/channel <id> <@频道|会话ID|none>"
+    ),
+    UsageLang => ("Usage: /lang <en|zh>", "用法：/lang <en|zh>"),
+    Yes => ("yes", "是"),
+    No => ("no", "否"),
+    ButtonBack => ("« Back", "« 返回"),
+    ButtonEnable => ("Enable", "启用"),
+    ButtonDisable => ("Disable", "停用"),
+    ButtonInfo => ("Info", "详情"),
+    ButtonFilters => ("Filters", "过滤"),
+    ButtonRemove => ("Remove", "删除"),
+    ButtonClearFilters => ("Clear filters", "清除过滤"),
+    ButtonRemoveKeepFiles => ("Remove, keep files", "删除，保留文件"),
+    ButtonRemoveTorrents => ("Remove torrents only", "仅删除种子"),
+    ButtonRemoveData => ("Remove torrents and data", "删除种子和数据"),
+    ButtonRemoveLibrary => ("Remove library folder too", "同时删除媒体库文件夹"),
+    ButtonCancel => ("Cancel", "取消"),
+    ButtonOpenMikan => ("Open in Mikan", "在蜜柑计划中打开"),
+    ButtonSkipGroup => ("Skip {}", "跳过 {}"),
+    ButtonPause => ("Pause show", "暂停订阅"),
+    SeasonProgress => ("Season {}: {} episodes, latest E{}", "第 {} 季：共 {} 集，最新 E{}"),
+    HelpHeader => ("These commands are supported:", "支持以下命令："),
+    UsagePoll => (
+        "Usage: /poll <id> <auto|minutes>",
+        "用法：/poll <id> <auto|分钟数>"
+    ),
+    UsageCheck => ("Usage: /check [id]", "用法：/check [id]"),
+    NothingToCheck => ("no enabled subscriptions.", "没有启用的订阅。"),
+    CheckSummary => (
+        "{} {}\nfound {}, queued {}, skipped {}",
+        "{} {}\n发现 {}，下载 {}，跳过 {}"
+    ),
+    CheckHidden => (
+        "{} downloaded before, present when subscribing or seen in earlier checks",
+        "{} 项已下载、订阅时已存在或之前已检查过"
+    ),
+    CheckUnchanged => ("feed unchanged since the last check", "订阅源自上次检查后没有变化"),
+    SkipFiltered => ("filtered by \"{}\"", "被“{}”过滤"),
+    SkipNoTorrent => ("no torrent", "没有种子"),
+    SkipDownloaded => ("already downloaded", "已下载"),
+    SkipIgnored => ("present when subscribing", "订阅时已存在"),
+    SkipImported => ("already in the library", "已在媒体库中"),
+    SkipBeforeFrom => ("before episode {}", "早于第 {} 集"),
+    SkipUnparseable => ("title not recognized, see /resolve", "无法识别标题，见 /resolve"),
+    QuarantineNotice => (
+        "{}\ncannot tell the episode of:\n{}\nPick it below or send /resolve {} <season> <episode>.",
+        "{}\n无法识别以下标题的集数：\n{}\n请在下方选择，或发送 /resolve {} <季> <集>。"
+    ),
+    ButtonIgnoreItem => ("Ignore", "忽略"),
+    UsageResolve => (
+        "Usage: /resolve [<item> <season> <episode>|<item> ignore]",
+        "用法：/resolve [<编号> <季> <集>|<编号> ignore]"
+    ),
+    NoQuarantine => ("no unrecognized titles.", "没有无法识别的标题。"),
+    QuarantineNotFound => ("Item not found. Check it with /resolve.", "未找到该项，请用 /resolve 确认。"),
+    ItemIgnored => ("ignored.", "已忽略。"),
+    UsageParseRule => (
+        "Usage: /parserule [add <fansub|id> <regex>|remove <rule_id>]\nThe regex needs a named group episode and may have title, season and version, e.g. \\[(?P<episode>\\d+)\\]",
+        "用法：/parserule [add <字幕组|id> <正则>|remove <规则ID>]\n正则须包含命名分组 episode，可包含 title、season 和 version，例如 \\[(?P<episode>\\d+)\\]"
+    ),
+    NoParseRules => ("no parsing rules.", "没有解析规则。"),
+    ParseRuleAdded => ("rule {} added.", "已添加规则 {}。"),
+    ParseRuleNotFound => ("Rule not found. Check it with /parserule.", "未找到规则，请用 /parserule 确认。"),
+    ScopeFansub => ("fansub {}", "字幕组 {}"),
+    ScopeBangumi => ("bangumi {}", "番剧 {}"),
+    UsageTestParse => ("Usage: /testparse [id] <title>", "用法：/testparse [id] <标题>"),
+    ParsedByRule => ("matched rule {} ({}): {}", "匹配规则 {}（{}）：{}"),
+    ParsedByBuiltIn => ("matched the built-in parser", "匹配内置解析器"),
+    ParsedByLearned => ("matched a rule learned from /resolve", "匹配从 /resolve 学到的规则"),
+    ParseFields => (
+        "title: {}\nseason: {}\nepisode: {}\nversion: {}\nfansub: {}",
+        "标题：{}\n季：{}\n集：{}\n版本：{}\n字幕组：{}"
+    ),
+    RuleLearned => (
+        "Similar titles will be recognized the same way.",
+        "之后相似的标题会按同样方式识别。"
+    ),
+    FileMismatch => (
+        "⚠ {}: the video in the torrent is named as {}, check the episode",
+        "⚠ {}：种子内视频文件名为 {}，请核对集数"
+    ),
+    TorrentSize => (
+        "torrent size {} is outside {} – {}",
+        "种子大小 {} 不在 {} – {} 范围内"
+    ),
+    TorrentForbiddenFile => ("torrent contains {}", "种子包含 {}"),
+    TorrentVideoCount => (
+        "torrent has {} videos for {} episodes",
+        "种子中有 {} 个视频，但对应 {} 集"
+    ),
+    TorrentWarning => ("⚠ {}: {}", "⚠ {}：{}"),
+    SkipRejected => ("rejected, {}", "已拒绝，{}"),
+    RejectedNotice => ("{}\nnot downloading {}: {}", "{}\n未下载 {}：{}"),
+    UsageSeed => (
+        "Usage: /seed [id] [ratio=<ratio>] [time=<minutes>] [upload=<KiB/s>] [remove=on|off]|default",
+        "用法：/seed [id] [ratio=<分享率>] [time=<分钟>] [upload=<KiB/s>] [remove=on|off]|default"
+    ),
+    SeedingGlobal => ("global seeding policy: {}", "全局做种策略：{}"),
+    SeedingPolicy => (
+        "seeding policy of {}: {}\nin effect: {}",
+        "{} 的做种策略：{}\n实际生效：{}"
+    ),
+    SkipSeen => ("seen in an earlier check", "之前已检查过"),
+    SkipRetrying => ("download failed before, retried later", "下载失败过，稍后重试"),
+    SkipGaveUp => ("download failed too often, see /retry", "下载失败次数过多，见 /retry"),
+    UsageRetry => ("Usage: /retry [id]", "用法：/retry [id]"),
+    NoRetries => ("no failed downloads.", "没有失败的下载。"),
+    RetryPending => (
+        "{}: failed {} times, next try in {} min ({})",
+        "{}：已失败 {} 次，{} 分钟后重试（{}）"
+    ),
+    RetryGaveUp => (
+        "{}: gave up after {} attempts ({})",
+        "{}：尝试 {} 次后放弃（{}）"
+    ),
+    RetryReset => ("retrying {} failed downloads now.", "正在重试 {} 个失败的下载。"),
+    GaveUpNotice => (
+        "{}\ngave up downloading {} after {} attempts: {}\nUse /retry {} to try again.",
+        "{}\n下载 {} 失败 {} 次，已放弃：{}\n使用 /retry {} 重试。"
+    ),
+    ErrBangumiNotFound => (
+        "Bangumi not found. Check the id with /list.",
+        "未找到番剧，请用 /list 确认 ID。"
+    ),
+    ErrInvalidValue => ("Invalid value: {}", "无效的值：{}"),
+    ErrStorage => (
+        "The database could not be read or written ({}). Check the DATABASE file and its permissions.",
+        "数据库读写失败（{}），请检查 DATABASE 文件及其权限。"
+    ),
+    ErrInvalidRssUrl => (
+        "{} is not a Mikan bangumi RSS link. Copy the RSS link of a bangumi page, e.g. https://mikanani.me/RSS/Bangumi?bangumiId=3330",
+        "{} 不是蜜柑计划的番剧 RSS 链接，请复制番剧页面的 RSS 链接，例如 https://mikanani.me/RSS/Bangumi?bangumiId=3330"
+    ),
+    ErrMikanPage => (
+        "The Mikan page could not be read ({}). Check the link or try again later.",
+        "无法解析蜜柑计划页面（{}），请检查链接或稍后重试。"
+    ),
+    ErrRequest => (
+        "Network request failed ({}). Check the URL, PROXY_URL and the network.",
+        "网络请求失败（{}），请检查链接、PROXY_URL 和网络连接。"
+    ),
+    ErrRss => (
+        "The URL did not return a valid RSS feed ({}). Use the RSS link from Mikan.",
+        "该链接没有返回有效的 RSS（{}），请使用蜜柑计划的 RSS 链接。"
+    ),
+    ErrQbitConfig => (
+        "qBittorrent is not configured ({}). Set QBIT_HOST, QBIT_USERNAME and QBIT_PASSWORD.",
+        "qBittorrent 未配置（{}），请设置 QBIT_HOST、QBIT_USERNAME 和 QBIT_PASSWORD。"
+    ),
+    ErrQbitUnreachable => (
+        "Cannot reach qBittorrent ({}). Check that it is running and QBIT_HOST is right.",
+        "无法连接 qBittorrent（{}），请确认其正在运行且 QBIT_HOST 正确。"
+    ),
+    ErrQbitLogin => (
+        "qBittorrent rejected the login. Check QBIT_USERNAME and QBIT_PASSWORD.",
+        "qBittorrent 登录失败，请检查 QBIT_USERNAME 和 QBIT_PASSWORD。"
+    ),
+    ErrMetadataTimeout => (
+        "Timed out waiting for the torrent metadata. The release may have no seeders, try again later.",
+        "等待种子元数据超时，可能没有做种者，请稍后重试。"
+    ),
+    ErrDownload => (
+        "The download could not be set up ({}). Check it in qBittorrent.",
+        "无法创建下载（{}），请在 qBittorrent 中检查。"
+    ),
+    ErrQbit => ("qBittorrent rejected the request: {}", "qBittorrent 拒绝了请求：{}"),
+    ErrTorrent => (
+        "The torrent file is invalid ({}). The release may have been removed.",
+        "种子文件无效（{}），该资源可能已被删除。"
+    ),
+    ErrParse => (
+        "The episode title could not be recognized ({}). Skip the release with /nc.",
+        "无法识别剧集标题（{}），可以用 /nc 跳过该资源。"
+    ),
+    ErrCheckRunning => (
+        "A check is already running, try again when it has finished.",
+        "已有检查正在进行，请稍后再试。"
+    ),
+    ErrIo => (
+        "File system error ({}). Check LIB_DIR, TMP_DIR and their permissions.",
+        "文件系统错误（{}），请检查 LIB_DIR、TMP_DIR 及其权限。"
+    ),
+}
+
+impl Locale {
+    /// Description of a bot command, `None` keeps the English one from
+    /// `Command`'s derive.
+    pub fn command_description(&self, command: &str) -> Option<&'static str> {
+        if *self == Locale::En {
+            return None;
+        }
+        let description = match command {
+            "help" => "显示此帮助。",
+            "list" => "列出所有番剧。",
+            "info" => "查看番剧详情。\n用法：/info <id>",
+            "add" => "添加番剧。\n用法：/add <rss_url> [all|new|<起始集数>]",
+            "remove" => "删除番剧，并选择如何处理其文件。\n用法：/remove <id>",
+            "enable" => "启用 RSS。\n用法：/enable <id>",
+            "disable" => "停用 RSS。\n用法：/disable <id>",
+            "nc" => "设置排除关键词。\n用法：/nc <id> <词1,词2,...>/none",
+            "backfill" => "下载订阅源中仍存在的旧剧集。\n用法：/backfill <id> <起始>-<结束>",
+            "get" => "下载单集。\n用法：/get <剧集链接>",
+            "scan" => "对比媒体库与数据库。\n用法：/scan [requeue]",
+            "follow" => "在此会话接收番剧通知。\n用法：/follow <id>",
+            "unfollow" => "停止在此会话接收番剧通知。\n用法：/unfollow <id>",
+            "notify" => "设置此会话的通知。\n用法：/notify <all|followed|off>",
+            "channel" => "将更新发布到频道。\n用法：/channel <id> <@频道|会话ID|none>",
+            "users" => "列出用户。",
+            "user" => "设置用户角色。\n用法：/user <用户ID> <admin|member|viewer|remove>",
+            "lang" => "设置语言。\n用法：/lang <en|zh>",
+            "poll" => "设置订阅源的轮询频率。\n用法：/poll <id> <auto|分钟数>",
+            "check" => "立即检查一个或全部订阅。\n用法：/check [id]",
+            "resolve" => "查看无法识别的标题，或指定其季和集。\n用法：/resolve [<编号> <季> <集>|<编号> ignore]",
+            "parserule" => "查看或编辑标题解析规则。\n用法：/parserule [add <字幕组|id> <正则>|remove <规则ID>]",
+            "testparse" => "显示标题的解析结果。\n用法：/testparse [id] <标题>",
+            "seed" => "查看或设置做种策略。\n用法：/seed [id] [ratio=<分享率>] [time=<分钟>] [upload=<KiB/s>] [remove=on|off]|default",
+            "retry" => "查看失败的下载，或立即重试番剧的失败下载。\n用法：/retry [id]",
+            _ => return None,
+        };
+        Some(description)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill() {
+        let text = fill(
+            Locale::ZhCn.text(Msg::EpisodeQueued),
+            &[&"葬送的芙莉莲 S01E20"],
+        );
+        assert_eq!(text, "葬送的芙莉莲 S01E20 已加入下载。");
+        assert_eq!(fill("{} - {}", &[&1]), "1 - {}");
+        assert_eq!(
+            Locale::from_language_code(Some("zh-hans")),
+            Some(Locale::ZhCn)
+        );
+        assert_eq!(Locale::from_language_code(Some("ja")), None);
+    }
+}
@@ -1,6 +1,15 @@
 pub mod database;
 pub mod downloader;
+pub mod error;
+pub mod i18n;
+pub mod library;
 pub mod mikan;
+pub mod organizer;
+pub mod rate_limit;
+pub mod scheduler;
+pub mod seeding;
+pub mod title_corpus;
 pub mod title_parser;
+pub mod updater;
 pub mod utils;
 pub mod bot;
\ No newline at end of file
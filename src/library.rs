@@ -0,0 +1,467 @@
+use crate::error::Result;
+use crate::{database, downloader::QbitDownloader, mikan, title_parser, updater, utils};
+use regex::Regex;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+const EPISODE_FILE_PATTERN: &str = r"S(\d{2,})E(\d{2,})";
+const SEASON_DIR_PATTERN: &str = r"^(?i:Season|S)\s*(\d{1,2})$";
+const BRACKET_PATTERN: &str = r"\[[^\]]*]|【[^】]*】|\([^)]*\)|（[^）]*）";
+// compiled once, file names are parsed for every file of a scan
+static EPISODE_FILE: LazyLock<Regex> = LazyLock::new(|| Regex::new(EPISODE_FILE_PATTERN).unwrap());
+static SEASON_DIR: LazyLock<Regex> = LazyLock::new(|| Regex::new(SEASON_DIR_PATTERN).unwrap());
+static BRACKET: LazyLock<Regex> = LazyLock::new(|| Regex::new(BRACKET_PATTERN).unwrap());
+// how alike a Mikan search hit must be to a folder to be imported as it
+const MIN_SIMILARITY: f64 = 0.5;
+
+#[derive(Debug, Clone)]
+pub struct MissingEpisode {
+    pub bangumi_id: u32,
+    pub title: String,
+    pub episode: database::Episode,
+}
+
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    /// recorded as downloaded but no video file found
+    pub missing: Vec<MissingEpisode>,
+    /// `SxxEyy` files of a known bangumi without an episode record
+    pub extra: Vec<PathBuf>,
+    /// files that do not belong to any bangumi or have no `SxxEyy` tag
+    pub unknown: Vec<PathBuf>,
+    /// (bangumi id, title, hash) of downloads without an episode record,
+    /// which cannot be checked
+    pub untracked: Vec<(u32, String, String)>,
+}
+
+pub fn parse_episode_file(file_name: &str) -> Option<(i8, i16)> {
+    let caps = EPISODE_FILE.captures_iter(file_name).last()?;
+    let season = caps.get(1)?.as_str().parse().ok()?;
+    let episode = caps.get(2)?.as_str().parse().ok()?;
+    Some((season, episode))
+}
+
+/// Whether `file` is a subtitle or other sidecar of one of `videos`, named
+/// after the video with a suffix like `.zh.ass`.
+fn is_sidecar(file: &Path, videos: &[PathBuf]) -> bool {
+    let name = match file.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return false,
+    };
+    videos.iter().any(|v| {
+        v.parent() == file.parent()
+            && v.file_stem()
+                .is_some_and(|stem| name.starts_with(&format!("{}.", stem.to_string_lossy())))
+    })
+}
+
+/// Walk `lib_dir` and compare the `{title}/Season {s}/{title} SxxEyy` files
+/// against the episode records of every bangumi.
+pub fn scan(db: &database::Client, lib_dir: &str) -> Result<ScanReport> {
+    let mut report = ScanReport::default();
+    let bangumis = db.get_bangumi_all()?;
+    let pending: HashSet<String> = db
+        .get_pending_organize_tasks()?
+        .into_iter()
+        .map(|t| t.hash)
+        .collect();
+    let mut present: HashMap<u32, HashSet<(i8, i16)>> = HashMap::new();
+    let lib_path = Path::new(lib_dir);
+    if lib_path.is_dir() {
+        for entry in std::fs::read_dir(lib_path)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let bangumi = match bangumis.iter().find(|b| b.title == name) {
+                Some(b) if path.is_dir() => b,
+                _ => {
+                    report.unknown.push(path);
+                    continue;
+                }
+            };
+            let found = present.entry(bangumi.id).or_default();
+            let files = utils::walk_files(&path)?;
+            let videos = files
+                .iter()
+                .filter(|f| utils::is_video(&f.to_string_lossy()))
+                .cloned()
+                .collect::<Vec<PathBuf>>();
+            for file in files {
+                let file_name = file
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                // reported along with their video, if at all
+                if !utils::is_video(&file_name) && is_sidecar(&file, &videos) {
+                    continue;
+                }
+                let (season, episode) = match parse_episode_file(&file_name) {
+                    Some(se) => se,
+                    None => {
+                        report.unknown.push(file);
+                        continue;
+                    }
+                };
+                if !bangumi
+                    .episodes
+                    .iter()
+                    .any(|e| e.season == season && e.episode == episode)
+                {
+                    report.extra.push(file);
+                } else if utils::is_video(&file_name) {
+                    found.insert((season, episode));
+                }
+            }
+        }
+    }
+    for b in bangumis.iter() {
+        for hash in b.downloaded.iter() {
+            if !b.episodes.iter().any(|e| &e.hash == hash) {
+                report.untracked.push((b.id, b.title.clone(), hash.clone()));
+            }
+        }
+        let found = present.get(&b.id);
+        for ep in b.episodes.iter() {
+            if pending.contains(&ep.hash)
+                || found.is_some_and(|f| f.contains(&(ep.season, ep.episode)))
+            {
+                continue;
+            }
+            report.missing.push(MissingEpisode {
+                bangumi_id: b.id,
+                title: b.title.clone(),
+                episode: ep.clone(),
+            });
+        }
+    }
+    Ok(report)
+}
+
+/// Give the downloads recorded before episodes were tracked an episode
+/// record, parsed from the name of their torrent in qBittorrent, so that
+/// [`scan`] can check them. Returns how many records were added.
+pub async fn backfill_episodes(db: &database::Client) -> Result<usize> {
+    let untracked = db
+        .get_bangumi_all()?
+        .into_iter()
+        .filter(|b| {
+            b.downloaded
+                .iter()
+                .any(|h| !b.episodes.iter().any(|e| &e.hash == h))
+        })
+        .collect::<Vec<_>>();
+    if untracked.is_empty() {
+        return Ok(0);
+    }
+    let rules = db.get_parse_rules()?;
+    let downloader = QbitDownloader::new().await?;
+    let mut added = 0;
+    for b in untracked.iter() {
+        for hash in b.downloaded.iter() {
+            if b.episodes.iter().any(|e| &e.hash == hash) {
+                continue;
+            }
+            let info = match downloader.torrent_info(hash).await {
+                Ok(Some(info)) => info,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::warn!("torrent info error: {} {:?}", hash, e);
+                    continue;
+                }
+            };
+            // renamed torrents are called `{title} SxxEyy`
+            let parsed = parse_episode_file(&info.name).or_else(|| {
+                updater::parse_episode(b, &rules, &info.name)
+                    .ok()
+                    .map(|r| (r.season, r.episode))
+            });
+            if let Some((season, episode)) = parsed {
+                db.add_episode(
+                    b.id,
+                    database::Episode {
+                        season,
+                        episode,
+                        hash: hash.clone(),
+                        title: info.name.clone(),
+                    },
+                )?;
+                added += 1;
+            }
+        }
+    }
+    Ok(added)
+}
+
+/// Drop the download records of missing episodes so the next RSS update
+/// fetches them again from the feed.
+pub fn requeue_missing(db: &database::Client, report: &ScanReport) -> Result<usize> {
+    for m in report.missing.iter() {
+        db.remove_episode(m.bangumi_id, &m.episode)?;
+    }
+    Ok(report.missing.len())
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// (bangumi id, folder name, mikan title, episode count)
+    pub imported: Vec<(u32, String, String, usize)>,
+    /// (path, reason)
+    pub unmatched: Vec<(PathBuf, String)>,
+}
+
+fn search_keyword(name: &str) -> String {
+    let name = BRACKET.replace_all(name, " ");
+    name.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn season_from_dir(path: &Path) -> Option<i8> {
+    let name = path.parent()?.file_name()?.to_str()?;
+    SEASON_DIR
+        .captures(name.trim())?
+        .get(1)?
+        .as_str()
+        .parse()
+        .ok()
+}
+
+/// How alike two titles are, from 0 to 1: the Dice coefficient of their
+/// character pairs, ignoring case, spaces and punctuation.
+fn similarity(a: &str, b: &str) -> f64 {
+    let pairs = |s: &str| {
+        let chars = s
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect::<Vec<char>>();
+        match chars.len() {
+            1 => vec![(chars[0], chars[0])],
+            _ => chars.windows(2).map(|w| (w[0], w[1])).collect(),
+        }
+    };
+    let (a, mut b) = (pairs(a), pairs(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let total = a.len() + b.len();
+    let mut common = 0;
+    for pair in a {
+        if let Some(i) = b.iter().position(|p| *p == pair) {
+            b.swap_remove(i);
+            common += 1;
+        }
+    }
+    (2 * common) as f64 / total as f64
+}
+
+/// Walk `dir`, one folder per show, and subscribe to the matching Mikan
+/// bangumi with the episodes found on disk marked as downloaded. Folders
+/// that fail are reported as unmatched.
+pub async fn import(
+    db: &database::Client,
+    parser: &mikan::MikanParser,
+    dir: &str,
+    not_contains: &[String],
+) -> Result<ImportReport> {
+    let mut report = ImportReport::default();
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+    for path in entries {
+        if !path.is_dir() {
+            report.unmatched.push((path, "not a folder".to_string()));
+            continue;
+        }
+        if let Err(e) = import_folder(db, parser, &path, not_contains, &mut report).await {
+            report.unmatched.push((path, format!("error: {}", e)));
+        }
+    }
+    Ok(report)
+}
+
+async fn import_folder(
+    db: &database::Client,
+    parser: &mikan::MikanParser,
+    path: &Path,
+    not_contains: &[String],
+    report: &mut ImportReport,
+) -> Result<()> {
+    let folder = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut keywords = vec![search_keyword(&folder)];
+    let mut episodes = BTreeSet::new();
+    for file in utils::walk_files(path)? {
+        let file_name = file
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if !utils::is_video(&file_name) {
+            continue;
+        }
+        if let Some(se) = parse_episode_file(&file_name) {
+            episodes.insert(se);
+            continue;
+        }
+        match title_parser::parse(&file_name) {
+            Ok(r) => {
+                let season = season_from_dir(&file).unwrap_or(r.season);
+                episodes.insert((season, r.episode));
+                for t in [r.title_zh, r.title_en, r.title_jp] {
+                    if !t.is_empty() && !keywords.contains(&t) {
+                        keywords.push(t);
+                    }
+                }
+            }
+            Err(_) => report
+                .unmatched
+                .push((file, "unrecognized episode".to_string())),
+        }
+    }
+    // the most similar search hit, taken only when it is similar enough
+    let mut best: Option<((u32, String), f64)> = None;
+    let mut search_error = None;
+    for keyword in keywords.iter().filter(|k| !k.is_empty()) {
+        let results = match parser.search(keyword).await {
+            Ok(results) => results,
+            Err(e) => {
+                log::error!("search error: {:?}", e);
+                search_error = Some(e);
+                continue;
+            }
+        };
+        for r in results {
+            let score = keywords
+                .iter()
+                .map(|k| similarity(k, &r.1))
+                .fold(0.0, f64::max);
+            if best.as_ref().is_none_or(|(_, s)| score > *s) {
+                best = Some((r, score));
+            }
+        }
+        if best.as_ref().is_some_and(|(_, s)| *s >= MIN_SIMILARITY) {
+            break;
+        }
+    }
+    let (id, mikan_title) = match (best, search_error) {
+        (Some((r, score)), _) if score >= MIN_SIMILARITY => r,
+        (Some(((_, title), _)), _) => {
+            let reason = format!("no similar Mikan match, closest: {}", title);
+            report.unmatched.push((path.to_path_buf(), reason));
+            return Ok(());
+        }
+        (None, Some(e)) => {
+            let reason = format!("search error: {}", e);
+            report.unmatched.push((path.to_path_buf(), reason));
+            return Ok(());
+        }
+        (None, None) => {
+            let reason = "no Mikan match".to_string();
+            report.unmatched.push((path.to_path_buf(), reason));
+            return Ok(());
+        }
+    };
+    let episodes = episodes
+        .into_iter()
+        .map(|(season, episode)| database::Episode {
+            season,
+            episode,
+            hash: String::new(),
+            title: String::new(),
+        })
+        .collect::<Vec<database::Episode>>();
+    let count = episodes.len();
+    if db.bangumi_exists(id)? {
+        for ep in episodes {
+            db.add_episode(id, ep)?;
+        }
+    } else {
+        let info = match parser.from_id(id).await {
+            Ok(info) => info,
+            Err(e) => {
+                let reason = format!("mikan error: {}", e);
+                report.unmatched.push((path.to_path_buf(), reason));
+                return Ok(());
+            }
+        };
+        db.insert_bangumi(database::Bangumi {
+            id,
+            // keep the folder name so new episodes land next to the old ones
+            title: folder.clone(),
+            weekday: info.weekday,
+            poster_url: info.poster_url,
+            downloaded: vec![],
+            rss_url: mikan::MikanParser::rss_url(id),
+            enabled: false,
+            not_contains: not_contains.to_vec(),
+            episodes,
+            mode: database::SubscribeMode::All,
+            ignored: vec![],
+            owners: vec![],
+            chats: vec![],
+            channel: None,
+            poll: database::PollMode::Auto,
+            feed: Default::default(),
+            seeding: Default::default(),
+        })?;
+    }
+    report.imported.push((id, folder, mikan_title, count));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_episode_file() {
+        assert_eq!(
+            parse_episode_file("我内心的糟糕念头 S02E13.mp4"),
+            Some((2, 13))
+        );
+        assert_eq!(parse_episode_file("Re ZERO S03E101.mkv"), Some((3, 101)));
+        assert_eq!(parse_episode_file("poster.jpg"), None);
+    }
+
+    #[test]
+    fn test_is_sidecar() {
+        let videos = vec![PathBuf::from("Frieren/Season 1/Frieren S01E01.mkv")];
+        for name in ["Frieren S01E01.ass", "Frieren S01E01.zh-Hans.srt"] {
+            let file = Path::new("Frieren/Season 1").join(name);
+            assert!(is_sidecar(&file, &videos), "{}", name);
+        }
+        assert!(!is_sidecar(
+            Path::new("Frieren/Season 1/Frieren S01E02.ass"),
+            &videos
+        ));
+        assert!(!is_sidecar(
+            Path::new("Frieren/Season 2/Frieren S01E01.ass"),
+            &videos
+        ));
+    }
+
+    #[test]
+    fn test_search_keyword() {
+        assert_eq!(
+            search_keyword("[Lilith-Raws] 葬送的芙莉莲 (2023) [BDRip]"),
+            "葬送的芙莉莲"
+        );
+        assert_eq!(
+            season_from_dir(Path::new("Frieren/Season 2/01.mkv")),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity("葬送的芙莉莲", "葬送的芙莉莲"), 1.0);
+        assert!(similarity("葬送的芙莉莲", "葬送的芙莉莲 第二季") >= MIN_SIMILARITY);
+        assert!(similarity("Sousou no Frieren", "sousou no frieren!") >= MIN_SIMILARITY);
+        assert!(similarity("葬送的芙莉莲", "药屋少女的呢喃") < MIN_SIMILARITY);
+        assert_eq!(similarity("", "葬送的芙莉莲"), 0.0);
+    }
+}
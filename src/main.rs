@@ -1,9 +1,7 @@
 use dotenv::dotenv;
-use lava_torrent::torrent::v1::Torrent;
 use ottobangumi::*;
 use std::env;
 use std::sync::Arc;
-use teloxide::requests::Requester;
 
 type BoxErr = Box<dyn std::error::Error + Send + Sync>;
 
@@ -12,14 +10,24 @@ async fn main() -> Result<(), BoxErr> {
     dotenv().ok();
     env_logger::init();
     let mut proxy: Option<reqwest::Proxy> = None;
-    if let Some(proxy_url) = env::var("PROXY_URL").ok() {
+    if let Ok(proxy_url) = env::var("PROXY_URL") {
         proxy = Some(reqwest::Proxy::all(&proxy_url)?);
         env::set_var("TELOXIDE_PROXY", proxy_url);
     }
-    let rss_interval: u32 = env::var("RSS_INTERVAL")
+    // feeds are polled every RSS_INTERVAL seconds, and every AIRING_INTERVAL
+    // seconds within AIRING_HOURS after the start of their airing weekday
+    let rss_interval: i64 = env::var("RSS_INTERVAL")
         .unwrap_or("3600".to_string())
         .parse()
         .unwrap();
+    let airing_interval: i64 = env::var("AIRING_INTERVAL")
+        .unwrap_or("900".to_string())
+        .parse()
+        .unwrap();
+    let airing_hours: i64 = env::var("AIRING_HOURS")
+        .unwrap_or("48".to_string())
+        .parse()
+        .unwrap();
     let db_path = env::var("DATABASE").unwrap_or("otto.db".to_string());
     let db = Arc::new(database::Client::new(&db_path)?);
     let config = Arc::new(bot::Config {
@@ -36,84 +44,111 @@ async fn main() -> Result<(), BoxErr> {
             .collect(),
         tmp_dir: env::var("TMP_DIR").unwrap_or("tmp".to_string()),
         lib_dir: env::var("LIB_DIR").unwrap(),
+        download_dir: env::var("DOWNLOAD_DIR").unwrap_or("downloads".to_string()),
+        organize_mode: env::var("ORGANIZE_MODE")
+            .unwrap_or_default()
+            .parse()
+            .unwrap(),
+        locale: env::var("LOCALE").unwrap_or_default().parse().unwrap(),
+        concurrency: env::var("UPDATE_CONCURRENCY")
+            .unwrap_or("4".to_string())
+            .parse()
+            .unwrap(),
+        retry_attempts: env::var("RETRY_ATTEMPTS")
+            .unwrap_or("5".to_string())
+            .parse()
+            .unwrap(),
+        torrent_size: {
+            let mib = |name: &str, default: &str| -> u64 {
+                env::var(name)
+                    .unwrap_or(default.to_string())
+                    .parse::<u64>()
+                    .unwrap()
+                    * 1024
+                    * 1024
+            };
+            // short-format anime can be a few MiB an episode
+            mib("MIN_TORRENT_MB", "0")..=mib("MAX_TORRENT_MB", "20480")
+        },
+        seeding: env::var("SEEDING").unwrap_or_default().parse().unwrap(),
     });
+    // minimum time between two requests to the same host
+    let rate_limit_ms: u64 = env::var("RATE_LIMIT_MS")
+        .unwrap_or("500".to_string())
+        .parse()
+        .unwrap();
+    rate_limit::init(tokio::time::Duration::from_millis(rate_limit_ms));
+    let organize_interval: u32 = env::var("ORGANIZE_INTERVAL")
+        .unwrap_or("300".to_string())
+        .parse()
+        .unwrap();
+    for id in config.user_ids.iter() {
+        db.seed_admin(*id)?;
+    }
+    let args: Vec<String> = env::args().collect();
+    if args.len() == 3 && args[1] == "import" {
+        let mut parser = mikan::MikanParser::new();
+        parser.set_proxy(config.proxy.clone())?;
+        let report = library::import(&db, &parser, &args[2], &config.not_contains).await?;
+        for (id, folder, title, count) in report.imported.iter() {
+            println!(
+                "imported: {} -> {} {} ({} episodes)",
+                folder, id, title, count
+            );
+        }
+        for (path, reason) in report.unmatched.iter() {
+            println!("unmatched: {} ({})", path.display(), reason);
+        }
+        return Ok(());
+    }
     let bot = bot::MyBot::new(config.clone(), db.clone()).await?;
     let tg_bot = bot.tg.clone();
     let (bot_handle, _) = bot.spawn();
 
-    tokio::spawn(async move {
-        loop {
-            let bangumis = db.get_rss_bangumi();
-            match bangumis {
-                Ok(bangumis) => {
-                    if bangumis.is_empty() {
-                        log::info!("no rss to update");
-                    }
-                    for b in bangumis {
-                        log::info!("update rss: {} - {}", b.id, b.title);
-                        if let Err(e) =
-                            update_rss(b, db.clone(), config.clone(), tg_bot.clone()).await
-                        {
-                            log::error!("update rss error: {:?}", e);
-                        }
-                    }
+    if config.organize_mode != organizer::OrganizeMode::Rename {
+        let db = db.clone();
+        let config = config.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = organizer::organize_pending(&db, config.organize_mode).await {
+                    log::error!("organize error: {:?}", e);
                 }
-                Err(e) => {
-                    log::error!("database error: {:?}", e);
+                tokio::time::sleep(tokio::time::Duration::from_secs(organize_interval as u64))
+                    .await;
+            }
+        });
+    }
+
+    let seeding_interval: u64 = env::var("SEEDING_INTERVAL")
+        .unwrap_or("600".to_string())
+        .parse()
+        .unwrap();
+    {
+        let db = db.clone();
+        let config = config.clone();
+        tokio::spawn(async move {
+            loop {
+                match seeding::maintain(&db, &config).await {
+                    Ok(removed) if !removed.is_empty() => {
+                        log::info!("removed {} seeded torrents", removed.len())
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::error!("seeding error: {:?}", e),
                 }
+                tokio::time::sleep(tokio::time::Duration::from_secs(seeding_interval)).await;
             }
-            tokio::time::sleep(tokio::time::Duration::from_secs(rss_interval as u64)).await;
-        }
-    });
+        });
+    }
+
+    scheduler::spawn(
+        scheduler::PollPlan::new(airing_interval, rss_interval, airing_hours),
+        db,
+        config,
+        tg_bot,
+    );
 
     if let Err(e) = tokio::try_join!(bot_handle) {
         log::error!("bot error: {:?}", e);
     }
     Ok(())
 }
-
-async fn update_rss(
-    b: database::Bangumi,
-    db: Arc<database::Client>,
-    cfg: Arc<bot::Config>,
-    tg: Arc<teloxide::Bot>,
-) -> Result<(), BoxErr> {
-    let rss = mikan::MikanRss::from_url(&b.rss_url)
-        .set_proxy(cfg.proxy.clone())?
-        .fetch()
-        .await?;
-    for ep in rss.items.iter().rev() {
-        if cfg.not_contains.iter().any(|s| ep.title.contains(s))
-            || ep.torrent_hash.is_empty()
-            || b.downloaded.contains(&ep.torrent_hash)
-        {
-            continue;
-        }
-        log::info!("starting download: {:?}", ep);
-        let ep_info = title_parser::parse(&ep.title)?;
-        let torrent_url = ep.torrent_url.as_str();
-        let torrent_name = torrent_url.split('/').last();
-        if torrent_name.is_none() {
-            log::error!("torrent name is none: {}", torrent_url);
-            continue;
-        }
-        let torrent_path = format!("{}/{}", cfg.tmp_dir, torrent_name.unwrap());
-        utils::download_file(torrent_url, &torrent_path, cfg.proxy.clone()).await?;
-        let save_dir = format!("{}/{}/Season {}", cfg.lib_dir, b.title, ep_info.season);
-        let save_name = format!("{} S{:02}E{:02}", b.title, ep_info.season, ep_info.episode);
-        let torrent_hash = Torrent::read_from_file(&torrent_path)?.info_hash();
-        let downloader = downloader::QbitDownloader::new().await?;
-        downloader
-            .download_by_torrent_to(&torrent_path, &torrent_hash, &save_dir, &save_name)
-            .await?;
-        db.add_downloaded(b.id, &torrent_hash)?;
-        for chat_id in cfg.user_ids.iter() {
-            tg.send_message(
-                teloxide::types::ChatId(*chat_id as i64),
-                format!("{} updated.", save_name),
-            )
-            .await?;
-        }
-    }
-    Ok(())
-}
@@ -1,16 +1,18 @@
+use crate::error::Result;
+use crate::rate_limit;
 use regex::Regex;
 use reqwest;
 use scraper::{Html, Selector};
-use std::error::Error;
 use thiserror::Error;
 
-type BoxErr = Box<dyn Error + Send + Sync>;
 const MIKAN_URL: &str = "https://mikanani.me";
 
 #[derive(Error, Debug)]
-enum MikanError {
+pub enum MikanError {
     #[error("Parse error")]
     ParseError,
+    #[error("Invalid RSS url {url}")]
+    InvalidRssUrl { url: String },
     #[error("Title not found")]
     TitleNotFound,
     #[error("Id not found")]
@@ -30,12 +32,19 @@ pub struct BangumiInfo {
     pub weekday: u8,
     pub poster_url: String,
     pub magnet: Option<String>,
+    pub episode_title: Option<String>,
 }
 
 pub struct MikanParser {
     client: reqwest::Client,
 }
 
+impl Default for MikanParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MikanParser {
     pub fn new() -> Self {
         let client = reqwest::Client::new();
@@ -48,55 +57,106 @@ impl MikanParser {
         };
         Ok(self)
     }
-    pub async fn from_id(&self, id: u32) -> Result<BangumiInfo, BoxErr> {
-        let resp = self
-            .client
-            .get(format!("{}/Home/Bangumi/{}", MIKAN_URL, id))
-            .send()
-            .await?;
+    pub async fn from_id(&self, id: u32) -> Result<BangumiInfo> {
+        let url = format!("{}/Home/Bangumi/{}", MIKAN_URL, id);
+        rate_limit::acquire(&url).await;
+        let resp = self.client.get(url).send().await?;
         let text = resp.text().await?;
         let document = Html::parse_document(&text);
         let bangumi = Self::parse_document(&document)?;
         if bangumi.id != id {
-            return Err(Box::new(MikanError::ParseError));
+            return Err(MikanError::ParseError.into());
         }
         Ok(bangumi)
     }
-    pub async fn from_rss_url(&self, url: &str) -> Result<BangumiInfo, BoxErr> {
+    pub async fn from_rss_url(&self, url: &str) -> Result<BangumiInfo> {
         let delimiters = ['?', '&'];
         let id = url
             .split_terminator(|c| delimiters.contains(&c))
             .find(|x| x.starts_with("bangumiId="))
             .and_then(|x| x.split('=').nth(1))
-            .ok_or(MikanError::IdNotFound)?
-            .parse::<u32>()?;
+            .and_then(|x| x.parse::<u32>().ok())
+            .ok_or(MikanError::InvalidRssUrl {
+                url: url.to_string(),
+            })?;
         self.from_id(id).await
     }
-    pub async fn from_url(&self, url: &str) -> Result<BangumiInfo, BoxErr> {
+    pub async fn from_url(&self, url: &str) -> Result<BangumiInfo> {
+        rate_limit::acquire(url).await;
         let resp = self.client.get(url).send().await?;
         let text = resp.text().await?;
-        let document = Html::parse_document(&text);
-        let id = Self::parse_id(&document)?;
-        let magnet = if url.contains("/Home/Episode/") {
-            Some(Self::parse_magnet(&document)?)
-        } else {
-            None
+        // `Html` is not `Send`, so it must be dropped before the next await
+        let (id, magnet, episode_title) = {
+            let document = Html::parse_document(&text);
+            let id = Self::parse_id(&document)?;
+            if url.contains("/Home/Episode/") {
+                (
+                    id,
+                    Some(Self::parse_magnet(&document)?),
+                    Some(Self::parse_episode_title(&document)?),
+                )
+            } else {
+                (id, None, None)
+            }
         };
         let mut bangumi = self.from_id(id).await?;
         bangumi.magnet = magnet;
+        bangumi.episode_title = episode_title;
         Ok(bangumi)
     }
-    pub fn parse_document(document: &Html) -> Result<BangumiInfo, BoxErr> {
+    pub fn rss_url(id: u32) -> String {
+        format!("{}/RSS/Bangumi?bangumiId={}", MIKAN_URL, id)
+    }
+    pub async fn search(&self, keyword: &str) -> Result<Vec<(u32, String)>> {
+        let url = format!("{}/Home/Search", MIKAN_URL);
+        rate_limit::acquire(&url).await;
+        let resp = self
+            .client
+            .get(url)
+            .query(&[("searchstr", keyword)])
+            .send()
+            .await?;
+        let text = resp.text().await?;
+        let document = Html::parse_document(&text);
+        Ok(Self::parse_search_result(&document))
+    }
+    fn parse_search_result(document: &Html) -> Vec<(u32, String)> {
+        let item_selector = Selector::parse("ul.an-ul li a").unwrap();
+        let text_selector = Selector::parse("div.an-text").unwrap();
+        let id_pattern = Regex::new(r"/Home/Bangumi/(\d+)").unwrap();
+        document
+            .select(&item_selector)
+            .filter_map(|x| {
+                let id = id_pattern
+                    .captures(x.value().attr("href")?)?
+                    .get(1)?
+                    .as_str()
+                    .parse::<u32>()
+                    .ok()?;
+                let text = x.select(&text_selector).next()?;
+                let title = text
+                    .value()
+                    .attr("title")
+                    .map(|t| t.to_string())
+                    .unwrap_or_else(|| text.text().collect::<String>())
+                    .trim()
+                    .to_string();
+                Some((id, title))
+            })
+            .collect()
+    }
+    pub fn parse_document(document: &Html) -> Result<BangumiInfo> {
         let bangumi_info = BangumiInfo {
             id: Self::parse_id(document)?,
             title: Self::parse_title(document)?,
             poster_url: Self::parse_poster_url(document)?,
             weekday: Self::parse_week_day(document)?,
             magnet: None,
+            episode_title: None,
         };
         Ok(bangumi_info)
     }
-    fn parse_title(document: &Html) -> Result<String, BoxErr> {
+    fn parse_title(document: &Html) -> Result<String> {
         let title_selector = Selector::parse("p.bangumi-title").unwrap();
         let title = document
             .select(&title_selector)
@@ -108,7 +168,7 @@ impl MikanParser {
             .to_string();
         Ok(title)
     }
-    fn parse_poster_url(document: &Html) -> Result<String, BoxErr> {
+    fn parse_poster_url(document: &Html) -> Result<String> {
         let poster_selector = Selector::parse("div.bangumi-poster").unwrap();
         let poster_url = document
             .select(&poster_selector)
@@ -123,7 +183,7 @@ impl MikanParser {
             .ok_or(MikanError::PosterNotFound)?;
         Ok(poster_url)
     }
-    fn parse_week_day(document: &Html) -> Result<u8, BoxErr> {
+    fn parse_week_day(document: &Html) -> Result<u8> {
         let info_selector = Selector::parse("p.bangumi-info").unwrap();
         let info_text = document
             .select(&info_selector)
@@ -131,7 +191,7 @@ impl MikanParser {
             .ok_or(MikanError::WeekDayNotFound)?
             .text()
             .collect::<String>();
-        let week_days = vec!['一', '二', '三', '四', '五', '六', '日'];
+        let week_days = ['一', '二', '三', '四', '五', '六', '日'];
         let ch = info_text
             .chars()
             .last()
@@ -143,7 +203,7 @@ impl MikanParser {
             + 1;
         Ok(weekday)
     }
-    fn parse_id(document: &Html) -> Result<u32, BoxErr> {
+    fn parse_id(document: &Html) -> Result<u32> {
         let id_selector = Selector::parse("a.mikan-rss").unwrap();
         let id_pattern = Regex::new(r"bangumiId=(\d+)").unwrap();
         let href = document
@@ -151,14 +211,25 @@ impl MikanParser {
             .next()
             .and_then(|x| x.value().attr("href"))
             .ok_or(MikanError::IdNotFound)?;
-        if let Some(id) = id_pattern.captures(href) {
-            if let Some(id) = id.get(1) {
-                return Ok(id.as_str().parse::<u32>()?);
-            }
-        }
-        Err(Box::new(MikanError::IdNotFound))
+        id_pattern
+            .captures(href)
+            .and_then(|c| c.get(1))
+            .and_then(|id| id.as_str().parse::<u32>().ok())
+            .ok_or(MikanError::IdNotFound.into())
+    }
+    fn parse_episode_title(document: &Html) -> Result<String> {
+        let title_selector = Selector::parse("p.episode-title").unwrap();
+        let title = document
+            .select(&title_selector)
+            .next()
+            .ok_or(MikanError::TitleNotFound)?
+            .text()
+            .collect::<String>()
+            .trim()
+            .to_string();
+        Ok(title)
     }
-    fn parse_magnet(document: &Html) -> Result<String, BoxErr> {
+    fn parse_magnet(document: &Html) -> Result<String> {
         let magnet_selector = Selector::parse("a.episode-btn").unwrap();
         let magnet = document
             .select(&magnet_selector)
@@ -176,11 +247,11 @@ mod tests {
     use std::env;
 
     #[tokio::test]
-    async fn test_bangumi() -> Result<(), BoxErr> {
+    async fn test_bangumi() -> Result<()> {
         let mut parser = MikanParser::new();
         dotenv().ok();
         let mut proxy: Option<reqwest::Proxy> = None;
-        if let Some(proxy_url) = env::var("PROXY_URL").ok() {
+        if let Ok(proxy_url) = env::var("PROXY_URL") {
             proxy = Some(reqwest::Proxy::all(proxy_url)?);
         }
         parser.set_proxy(proxy.clone())?;
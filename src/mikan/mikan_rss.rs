@@ -1,4 +1,16 @@
-type BoxErr = Box<dyn std::error::Error + Send + Sync>;
+use crate::error::Result;
+use crate::rate_limit;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+/// 64-bit FNV-1a, unlike `DefaultHasher` the same across Rust releases,
+/// which matters for hashes kept in the database.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
 
 #[derive(Debug)]
 pub struct MikanRss {
@@ -21,6 +33,51 @@ pub struct RssEpisode {
     pub description: String,
     pub torrent_url: String,
     pub torrent_hash: String,
+    pub guid: String,
+    pub pub_date: Option<String>,
+}
+
+/// What is remembered of a feed between fetches.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FeedCache {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_hash: Option<String>,
+    /// newest item processed so far
+    pub last_guid: Option<String>,
+    pub last_pub_date: Option<String>,
+}
+
+impl FeedCache {
+    /// Index of the first item processed before, or the length of `items`
+    /// if none was. Feeds list the newest items first.
+    pub fn seen_from(&self, items: &[RssEpisode]) -> usize {
+        if let Some(i) = self
+            .last_guid
+            .as_ref()
+            .and_then(|guid| items.iter().position(|ep| &ep.guid == guid))
+        {
+            return i;
+        }
+        let last = match self.last_pub_date.as_deref().and_then(parse_date) {
+            Some(last) => last,
+            None => return items.len(),
+        };
+        items
+            .iter()
+            .position(|ep| ep.pub_date.as_deref().and_then(parse_date).is_some_and(|d| d <= last))
+            .unwrap_or(items.len())
+    }
+    pub fn mark_seen(&mut self, items: &[RssEpisode]) {
+        if let Some(ep) = items.first() {
+            self.last_guid = Some(ep.guid.clone());
+            self.last_pub_date = ep.pub_date.clone();
+        }
+    }
+}
+
+fn parse_date(date: &str) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    chrono::DateTime::parse_from_rfc2822(date).ok()
 }
 
 impl From<rss::Channel> for RssBangumi {
@@ -32,12 +89,19 @@ impl From<rss::Channel> for RssBangumi {
                 let i = item.clone();
                 let enclosure = i.enclosure.unwrap_or(rss::Enclosure::default());
                 let torrent_url = enclosure.url.to_string();
-                let torrent_name = torrent_url.split('/').last().unwrap_or("").to_string();
+                let torrent_name = torrent_url.split('/').next_back().unwrap_or("").to_string();
                 let torrent_hash = torrent_name.split('.').next().unwrap_or("").to_string();
                 RssEpisode {
                     title: item.title().unwrap_or("").to_string(),
                     link: item.link().unwrap_or("").to_string(),
                     description: item.description().unwrap_or("").to_string(),
+                    guid: item
+                        .guid()
+                        .map(|g| g.value())
+                        .or(item.link())
+                        .unwrap_or(&torrent_hash)
+                        .to_string(),
+                    pub_date: item.pub_date().map(|d| d.to_string()),
                     torrent_url,
                     torrent_hash,
                 }
@@ -69,9 +133,88 @@ impl MikanRss {
         };
         Ok(self)
     }
-    pub async fn fetch(&self) -> Result<RssBangumi, BoxErr> {
+    pub async fn fetch(&self) -> Result<RssBangumi> {
+        rate_limit::acquire(&self.url).await;
         let body = self.client.get(&self.url).send().await?.text().await?;
         let channel = rss::Channel::read_from(body.as_bytes())?;
         Ok(RssBangumi::from(channel))
     }
+    /// Fetch the feed unless it is unchanged since `cache` was taken, in
+    /// which case `None` is returned along with the refreshed cache.
+    pub async fn fetch_if_changed(
+        &self,
+        cache: &FeedCache,
+    ) -> Result<(Option<RssBangumi>, FeedCache)> {
+        rate_limit::acquire(&self.url).await;
+        let mut request = self.client.get(&self.url);
+        if let Some(etag) = cache.etag.as_ref() {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = cache.last_modified.as_ref() {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let resp = request.send().await?;
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            return Ok((None, cache.clone()));
+        }
+        let resp = resp.error_for_status()?;
+        let header = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        let mut cache = FeedCache {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            ..cache.clone()
+        };
+        let body = resp.text().await?;
+        let content_hash = format!("{:016x}", fnv1a(body.as_bytes()));
+        if cache.content_hash.as_ref() == Some(&content_hash) {
+            return Ok((None, cache));
+        }
+        cache.content_hash = Some(content_hash);
+        let channel = rss::Channel::read_from(body.as_bytes())?;
+        Ok((Some(RssBangumi::from(channel)), cache))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(guid: &str, pub_date: &str) -> RssEpisode {
+        RssEpisode {
+            title: guid.to_string(),
+            link: String::new(),
+            description: String::new(),
+            torrent_url: String::new(),
+            torrent_hash: String::new(),
+            guid: guid.to_string(),
+            pub_date: Some(pub_date.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_seen_from() {
+        let items = vec![
+            episode("3", "Sun, 07 Apr 2024 12:00:00 +0800"),
+            episode("2", "Sun, 31 Mar 2024 12:00:00 +0800"),
+            episode("1", "Sun, 24 Mar 2024 12:00:00 +0800"),
+        ];
+        let mut cache = FeedCache::default();
+        assert_eq!(cache.seen_from(&items), 3);
+        cache.mark_seen(&items[1..]);
+        assert_eq!(cache.seen_from(&items), 1);
+        // the newest item left the feed, fall back to the date
+        cache.last_guid = Some("4".to_string());
+        assert_eq!(cache.seen_from(&items), 1);
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
 }
@@ -1,4 +1,4 @@
 mod mikan_parser;
 mod mikan_rss;
-pub use mikan_parser::MikanParser;
-pub use mikan_rss::MikanRss;
+pub use mikan_parser::{MikanError, MikanParser};
+pub use mikan_rss::{FeedCache, MikanRss, RssBangumi, RssEpisode};
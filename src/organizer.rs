@@ -0,0 +1,309 @@
+use crate::error::Result;
+use crate::{database, downloader::QbitDownloader, utils};
+use std::{path::Path, str::FromStr};
+use thiserror::Error;
+
+/// How finished downloads end up in the library.
+///
+/// `Rename` renames the files inside the torrent (the download directory is
+/// the library), the others leave the torrent untouched in the download
+/// directory and place the video and its sidecars into the library once the
+/// torrent has completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OrganizeMode {
+    #[default]
+    Rename,
+    Hardlink,
+    Copy,
+    Symlink,
+}
+
+#[derive(Error, Debug)]
+pub enum OrganizeError {
+    #[error("Unknown organize mode {mode}")]
+    UnknownMode { mode: String },
+    #[error("Invalid path {path}")]
+    InvalidPath { path: String },
+}
+
+impl FromStr for OrganizeMode {
+    type Err = OrganizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "" | "rename" | "move" => Ok(OrganizeMode::Rename),
+            "hardlink" | "link" => Ok(OrganizeMode::Hardlink),
+            "copy" => Ok(OrganizeMode::Copy),
+            "symlink" => Ok(OrganizeMode::Symlink),
+            _ => Err(OrganizeError::UnknownMode {
+                mode: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// What goes along with a removed subscription.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoveMode {
+    /// torrents keep seeding and every file stays
+    KeepFiles,
+    /// the torrents, leaving their data
+    Torrents,
+    /// the torrents with their data and the files placed in the library
+    TorrentsAndData,
+    /// all of the above and the library folder of the bangumi
+    Library,
+}
+
+impl RemoveMode {
+    pub const ALL: [RemoveMode; 4] = [
+        RemoveMode::KeepFiles,
+        RemoveMode::Torrents,
+        RemoveMode::TorrentsAndData,
+        RemoveMode::Library,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RemoveMode::KeepFiles => "keep",
+            RemoveMode::Torrents => "torrents",
+            RemoveMode::TorrentsAndData => "data",
+            RemoveMode::Library => "library",
+        }
+    }
+}
+
+impl FromStr for RemoveMode {
+    type Err = OrganizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RemoveMode::ALL
+            .into_iter()
+            .find(|m| m.as_str() == s)
+            .ok_or(OrganizeError::UnknownMode {
+                mode: s.to_string(),
+            })
+    }
+}
+
+pub fn place_file(src: &Path, dest: &Path, mode: OrganizeMode) -> std::io::Result<()> {
+    if dest.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match mode {
+        OrganizeMode::Rename => std::fs::rename(src, dest),
+        OrganizeMode::Hardlink => std::fs::hard_link(src, dest),
+        OrganizeMode::Copy => std::fs::copy(src, dest).map(|_| ()),
+        #[cfg(unix)]
+        OrganizeMode::Symlink => std::os::unix::fs::symlink(src, dest),
+        #[cfg(windows)]
+        OrganizeMode::Symlink => std::os::windows::fs::symlink_file(src, dest),
+    }
+}
+
+/// Place the files of every completed pending task into the library and
+/// return the tasks that were organized. A task that fails is logged and
+/// tried again in the next pass, without holding up the others.
+pub async fn organize_pending(
+    db: &database::Client,
+    mode: OrganizeMode,
+) -> Result<Vec<database::OrganizeTask>> {
+    let tasks = db.get_pending_organize_tasks()?;
+    let mut organized = Vec::new();
+    if tasks.is_empty() {
+        return Ok(organized);
+    }
+    let downloader = QbitDownloader::new().await?;
+    for task in tasks {
+        match organize_task(db, &downloader, &task, mode).await {
+            Ok(Some(placed)) => organized.push(database::OrganizeTask {
+                done: true,
+                files: placed,
+                ..task
+            }),
+            Ok(None) => {}
+            Err(e) => log::error!("organize error: {} {:?}", task.hash, e),
+        }
+    }
+    Ok(organized)
+}
+
+/// Place the files of a task, returning `None` while its torrent is not
+/// complete yet. A task whose torrent was deleted from qBittorrent is
+/// dropped, there is nothing left to place.
+async fn organize_task(
+    db: &database::Client,
+    downloader: &QbitDownloader,
+    task: &database::OrganizeTask,
+    mode: OrganizeMode,
+) -> Result<Option<Vec<String>>> {
+    let info = match downloader.torrent_info(&task.hash).await? {
+        Some(info) => info,
+        None => {
+            log::warn!(
+                "torrent not found in downloader, dropping task: {}",
+                task.hash
+            );
+            db.delete_organize_task(&task.hash)?;
+            return Ok(None);
+        }
+    };
+    if info.progress < 1.0 {
+        return Ok(None);
+    }
+    let files = downloader.torrent_files(&task.hash).await?;
+    let mut placed = Vec::new();
+    for (src, new_name) in QbitDownloader::rename_plan(&files, &task.save_name)? {
+        let src = Path::new(&info.save_path).join(src);
+        let dest = Path::new(&task.save_dir).join(new_name);
+        place_file(&src, &dest, mode)?;
+        placed.push(
+            dest.to_str()
+                .ok_or(OrganizeError::InvalidPath {
+                    path: dest.to_string_lossy().to_string(),
+                })?
+                .to_string(),
+        );
+    }
+    log::info!("organized: {} -> {:?}", task.hash, placed);
+    db.set_organize_task_done(&task.hash, placed.clone())?;
+    Ok(Some(placed))
+}
+
+/// Remove the library files placed for a bangumi, leaving the torrents and
+/// their data in the download directory alone.
+pub async fn cleanup(db: &database::Client, bangumi_id: u32) -> Result<()> {
+    for task in db.get_organize_tasks(bangumi_id)? {
+        for f in task.files.iter() {
+            utils::delete_file(f).await?;
+        }
+        // only succeeds when nothing else is left in the season folder
+        std::fs::remove_dir(&task.save_dir).ok();
+    }
+    db.delete_organize_tasks(bangumi_id)?;
+    Ok(())
+}
+
+/// The torrents downloaded for a bangumi. Older downloads have no episode
+/// record and are only in `downloaded`.
+pub fn torrent_hashes(b: &database::Bangumi) -> Vec<String> {
+    let mut hashes = b
+        .episodes
+        .iter()
+        .map(|e| e.hash.clone())
+        .chain(b.downloaded.iter().cloned())
+        .filter(|h| !h.is_empty())
+        .map(|h| h.to_lowercase())
+        .collect::<Vec<String>>();
+    hashes.sort();
+    hashes.dedup();
+    hashes
+}
+
+/// The library folder of a bangumi, refusing titles that would point
+/// anywhere else.
+pub fn library_dir(lib_dir: &str, title: &str) -> Result<std::path::PathBuf> {
+    let mut components = Path::new(title).components();
+    match (components.next(), components.next()) {
+        (Some(std::path::Component::Normal(_)), None) => Ok(Path::new(lib_dir).join(title)),
+        _ => Err(OrganizeError::InvalidPath {
+            path: title.to_string(),
+        }
+        .into()),
+    }
+}
+
+/// Remove what belongs to a bangumi according to `mode`, before the bangumi
+/// itself is deleted.
+pub async fn remove_bangumi(
+    db: &database::Client,
+    b: &database::Bangumi,
+    lib_dir: &str,
+    mode: RemoveMode,
+) -> Result<()> {
+    if mode == RemoveMode::KeepFiles {
+        db.delete_organize_tasks(b.id)?;
+        return Ok(());
+    }
+    // fail before deleting anything rather than leave a half removed bangumi
+    let library = match mode {
+        RemoveMode::Library => Some(library_dir(lib_dir, &b.title)?),
+        _ => None,
+    };
+    let hashes = torrent_hashes(b);
+    if !hashes.is_empty() {
+        let downloader = QbitDownloader::new().await?;
+        downloader
+            .delete(&hashes, mode != RemoveMode::Torrents)
+            .await?;
+    }
+    if mode == RemoveMode::Torrents {
+        db.delete_organize_tasks(b.id)?;
+    } else {
+        cleanup(db, b.id).await?;
+    }
+    if let Some(dir) = library.filter(|dir| dir.is_dir()) {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_place_file() {
+        let dir = std::env::temp_dir().join("otto_organizer_test");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("[ANi] test - 01.mp4");
+        std::fs::write(&src, b"video").unwrap();
+        for (mode, name) in [
+            (OrganizeMode::Hardlink, "hardlink"),
+            (OrganizeMode::Copy, "copy"),
+            (OrganizeMode::Symlink, "symlink"),
+        ] {
+            assert_eq!(name.parse::<OrganizeMode>().unwrap(), mode);
+            let dest = dir.join(name).join("Season 1").join("test S01E01.mp4");
+            place_file(&src, &dest, mode).unwrap();
+            assert_eq!(std::fs::read(&dest).unwrap(), b"video");
+        }
+        assert!(src.exists());
+        assert!("torrent".parse::<OrganizeMode>().is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_torrent_hashes() {
+        let b: database::Bangumi = serde_json::from_value(serde_json::json!({
+            "id": 1, "title": "Frieren", "weekday": 5, "poster_url": "",
+            "rss_url": "", "enabled": true, "not_contains": [],
+            "downloaded": ["AAA", "bbb"],
+            "episodes": [
+                { "season": 1, "episode": 1, "hash": "aaa", "title": "" },
+                { "season": 1, "episode": 2, "hash": "", "title": "" },
+                { "season": 1, "episode": 3, "hash": "ccc", "title": "" },
+            ],
+        }))
+        .unwrap();
+        assert_eq!(torrent_hashes(&b), vec!["aaa", "bbb", "ccc"]);
+    }
+
+    #[test]
+    fn test_library_dir() {
+        assert_eq!(
+            library_dir("/lib", "Frieren").unwrap(),
+            Path::new("/lib/Frieren")
+        );
+        for title in ["", "..", "a/b", "/etc", "."] {
+            assert!(library_dir("/lib", title).is_err(), "{}", title);
+        }
+        for mode in RemoveMode::ALL {
+            assert_eq!(mode.as_str().parse::<RemoveMode>().unwrap(), mode);
+        }
+    }
+}
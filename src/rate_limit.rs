@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use tokio::time::{Duration, Instant};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(500);
+
+static LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+/// Spaces out requests to the same host by at least `interval`, however many
+/// tasks are making them.
+pub struct RateLimiter {
+    interval: Duration,
+    next: Mutex<HashMap<String, Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(interval: Duration) -> Self {
+        RateLimiter {
+            interval,
+            next: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Reserve the next free slot for the host of `url` and return when it
+    /// starts.
+    fn reserve(&self, url: &str) -> Instant {
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_string()))
+            .unwrap_or_default();
+        let now = Instant::now();
+        let mut next = self.next.lock().unwrap();
+        let slot = next.get(&host).copied().unwrap_or(now).max(now);
+        next.insert(host, slot + self.interval);
+        slot
+    }
+    pub async fn acquire(&self, url: &str) {
+        tokio::time::sleep_until(self.reserve(url)).await;
+    }
+}
+
+/// Set the interval of the shared limiter, only effective before the first
+/// request.
+pub fn init(interval: Duration) {
+    if LIMITER.set(RateLimiter::new(interval)).is_err() {
+        log::warn!("rate limiter already initialized");
+    }
+}
+
+/// Wait for a slot of the limiter shared by every request to Mikan.
+pub async fn acquire(url: &str) {
+    LIMITER
+        .get_or_init(|| RateLimiter::new(DEFAULT_INTERVAL))
+        .acquire(url)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve() {
+        let limiter = RateLimiter::new(Duration::from_secs(1));
+        let first = limiter.reserve("https://mikanani.me/RSS/Bangumi?bangumiId=3330");
+        let second = limiter.reserve("https://mikanani.me/Home/Bangumi/3330");
+        let other = limiter.reserve("https://example.com/a.torrent");
+        assert_eq!(second - first, Duration::from_secs(1));
+        assert!(other < second);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{bot, database, updater};
+use chrono::{DateTime, Datelike, FixedOffset, Timelike, Utc};
+use clokwerk::{AsyncScheduler, TimeUnits};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Mikan lists the airing weekday in China Standard Time
+const AIRING_UTC_OFFSET: i32 = 8 * 3600;
+
+/// Decides which feeds are due: the ones airing in the last `airing_hours`
+/// are polled every `airing_interval` seconds, the rest every
+/// `idle_interval` seconds, unless a subscription sets its own interval.
+pub struct PollPlan {
+    pub airing_interval: i64,
+    pub idle_interval: i64,
+    pub airing_hours: i64,
+    last_checked: HashMap<u32, DateTime<Utc>>,
+}
+
+impl PollPlan {
+    pub fn new(airing_interval: i64, idle_interval: i64, airing_hours: i64) -> Self {
+        PollPlan {
+            airing_interval,
+            idle_interval,
+            airing_hours,
+            last_checked: HashMap::new(),
+        }
+    }
+    /// Whether `now` falls within `airing_hours` after the start of the
+    /// airing weekday (1 is Monday), unknown weekdays never do.
+    pub fn is_airing(&self, weekday: u8, now: DateTime<Utc>) -> bool {
+        if !(1..=7).contains(&weekday) {
+            return false;
+        }
+        let local = now.with_timezone(&FixedOffset::east_opt(AIRING_UTC_OFFSET).unwrap());
+        let days = (local.weekday().number_from_monday() as i64 - weekday as i64).rem_euclid(7);
+        days * 24 + (local.hour() as i64) < self.airing_hours
+    }
+    pub fn interval(&self, b: &database::Bangumi, now: DateTime<Utc>) -> i64 {
+        match b.poll {
+            database::PollMode::Every(minutes) => minutes as i64 * 60,
+            database::PollMode::Auto if self.is_airing(b.weekday, now) => self.airing_interval,
+            database::PollMode::Auto => self.idle_interval,
+        }
+    }
+    pub fn due(&self, bangumis: &[database::Bangumi], now: DateTime<Utc>) -> Vec<u32> {
+        bangumis
+            .iter()
+            .filter(|b| match self.last_checked.get(&b.id) {
+                Some(last) => (now - *last).num_seconds() >= self.interval(b, now),
+                None => true,
+            })
+            .map(|b| b.id)
+            .collect()
+    }
+    pub fn mark_checked(&mut self, ids: &[u32], now: DateTime<Utc>) {
+        for id in ids {
+            self.last_checked.insert(*id, now);
+        }
+    }
+}
+
+/// Every minute, check the enabled subscriptions that are due.
+pub fn spawn(
+    plan: PollPlan,
+    db: Arc<database::Client>,
+    cfg: Arc<bot::Config>,
+    tg: Arc<teloxide::Bot>,
+) -> tokio::task::JoinHandle<()> {
+    let plan = Arc::new(Mutex::new(plan));
+    let mut scheduler = AsyncScheduler::with_tz(Utc);
+    scheduler.every(1.minute()).run(move || {
+        let (plan, db, cfg, tg) = (plan.clone(), db.clone(), cfg.clone(), tg.clone());
+        async move {
+            let now = Utc::now();
+            let due = match db.get_rss_bangumi() {
+                Ok(bangumis) => plan.lock().unwrap().due(&bangumis, now),
+                Err(e) => {
+                    log::error!("database error: {:?}", e);
+                    return;
+                }
+            };
+            if due.is_empty() {
+                return;
+            }
+            match updater::check(Some(due.clone()), db, cfg, tg).await {
+                Ok(_) => plan.lock().unwrap().mark_checked(&due, now),
+                // a running `/check` covers these, try again on the next tick
+                Err(e) => log::warn!("check error: {:?}", e),
+            }
+        }
+    });
+    tokio::spawn(async move {
+        loop {
+            scheduler.run_pending().await;
+            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_is_airing() {
+        let plan = PollPlan::new(600, 3600, 48);
+        // 2024-04-07 is a Sunday, 20:00 in UTC+8
+        let sunday = Utc.with_ymd_and_hms(2024, 4, 7, 12, 0, 0).unwrap();
+        assert!(plan.is_airing(7, sunday));
+        // Tuesday 03:00 in UTC+8 is 51 hours after Sunday started
+        let tuesday = Utc.with_ymd_and_hms(2024, 4, 8, 19, 0, 0).unwrap();
+        assert!(!plan.is_airing(7, tuesday));
+        assert!(plan.is_airing(1, tuesday));
+        assert!(!plan.is_airing(3, sunday));
+        assert!(!plan.is_airing(0, sunday));
+    }
+}
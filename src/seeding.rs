@@ -0,0 +1,272 @@
+use crate::error::Result;
+use crate::{bot, database, downloader::QbitDownloader, organizer};
+use qbit_api_rs::types::torrents::{InfoResponseItem, InfoState};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+
+// KiB/s, far above any real connection and still fine in bytes as i64
+const MAX_UPLOAD: u64 = 1 << 32;
+
+/// How the torrents of a bangumi are seeded. Unset fields of a bangumi fall
+/// back to the global policy, unset global ones to the qBittorrent settings.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SeedingPolicy {
+    pub ratio: Option<f64>,
+    /// minutes
+    pub time: Option<i64>,
+    /// KiB/s
+    pub upload: Option<u64>,
+    /// remove the torrent, keeping its data, once it finished seeding
+    pub remove: Option<bool>,
+}
+
+#[derive(Error, Debug)]
+pub enum SeedingError {
+    #[error("Invalid seeding policy {value}")]
+    InvalidPolicy { value: String },
+}
+
+impl SeedingPolicy {
+    /// This policy with the unset fields taken from `fallback`.
+    pub fn or(&self, fallback: &SeedingPolicy) -> SeedingPolicy {
+        SeedingPolicy {
+            ratio: self.ratio.or(fallback.ratio),
+            time: self.time.or(fallback.time),
+            upload: self.upload.or(fallback.upload),
+            remove: self.remove.or(fallback.remove),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == SeedingPolicy::default()
+    }
+
+    /// Whether the limits of a torrent are the ones of this policy.
+    fn applied(&self, info: &InfoResponseItem) -> bool {
+        self.ratio
+            .is_none_or(|r| (info.ratio_limit - r).abs() < 0.01)
+            && self.time.is_none_or(|t| info.seeding_time_limit == t)
+            && self
+                .upload
+                .is_none_or(|u| info.up_limit as u64 == u.saturating_mul(1024))
+    }
+
+    /// Whether a completed torrent with `ratio` that seeded for `minutes` is
+    /// done. Without limits a torrent is done as soon as it completed.
+    pub fn finished(&self, ratio: f64, minutes: i64) -> bool {
+        match (self.ratio, self.time) {
+            (None, None) => true,
+            (ratio_limit, time_limit) => {
+                ratio_limit.is_some_and(|r| ratio >= r) || time_limit.is_some_and(|t| minutes >= t)
+            }
+        }
+    }
+}
+
+impl FromStr for SeedingPolicy {
+    type Err = SeedingError;
+
+    /// `ratio=<ratio> time=<minutes> upload=<KiB/s> remove=<on|off>`, any of
+    /// them, or `default` for none.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = SeedingPolicy::default();
+        if s.trim() == "default" {
+            return Ok(policy);
+        }
+        for token in s.split_whitespace() {
+            let invalid = || SeedingError::InvalidPolicy {
+                value: token.to_string(),
+            };
+            let (key, value) = token.split_once('=').ok_or_else(invalid)?;
+            match key {
+                "ratio" => {
+                    let ratio = value.parse::<f64>().map_err(|_| invalid())?;
+                    if !ratio.is_finite() || ratio < 0.0 {
+                        return Err(invalid());
+                    }
+                    policy.ratio = Some(ratio);
+                }
+                "time" => {
+                    let time = value.parse::<i64>().map_err(|_| invalid())?;
+                    if time < 0 {
+                        return Err(invalid());
+                    }
+                    policy.time = Some(time);
+                }
+                "upload" => {
+                    let upload = value.parse::<u64>().map_err(|_| invalid())?;
+                    if upload > MAX_UPLOAD {
+                        return Err(invalid());
+                    }
+                    policy.upload = Some(upload);
+                }
+                "remove" => {
+                    policy.remove = Some(match value {
+                        "on" | "true" | "yes" => true,
+                        "off" | "false" | "no" => false,
+                        _ => return Err(invalid()),
+                    })
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for SeedingPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        parts.extend(self.ratio.map(|r| format!("ratio={}", r)));
+        parts.extend(self.time.map(|t| format!("time={}", t)));
+        parts.extend(self.upload.map(|u| format!("upload={}", u)));
+        parts.extend(
+            self.remove
+                .map(|r| format!("remove={}", if r { "on" } else { "off" })),
+        );
+        if parts.is_empty() {
+            write!(f, "default")
+        } else {
+            write!(f, "{}", parts.join(" "))
+        }
+    }
+}
+
+/// Set the limits of a policy on torrents, leaving the unset ones alone.
+pub async fn apply(
+    downloader: &QbitDownloader,
+    hashes: &[String],
+    policy: &SeedingPolicy,
+) -> Result<()> {
+    downloader
+        .set_seeding_limits(hashes, policy.ratio, policy.time, policy.upload)
+        .await
+}
+
+/// Bring the torrents of every bangumi in line with its seeding policy, and
+/// remove the torrents that finished seeding where the policy says so,
+/// returning their hashes. Torrents still waiting to be organized are kept.
+/// A bangumi or torrent that fails is logged and left for the next pass.
+pub async fn maintain(db: &database::Client, cfg: &bot::Config) -> Result<Vec<String>> {
+    let policies = db
+        .get_bangumi_all()?
+        .into_iter()
+        .map(|b| {
+            let policy = b.seeding.or(&cfg.seeding);
+            (b, policy)
+        })
+        .filter(|(b, policy)| !policy.is_empty() && !organizer::torrent_hashes(b).is_empty())
+        .collect::<Vec<_>>();
+    let mut removed = Vec::new();
+    if policies.is_empty() {
+        return Ok(removed);
+    }
+    let downloader = QbitDownloader::new().await?;
+    for (b, policy) in policies {
+        let infos = match downloader
+            .torrents_info(&organizer::torrent_hashes(&b))
+            .await
+        {
+            Ok(infos) => infos,
+            Err(e) => {
+                log::error!("seeding info error: {} {:?}", b.title, e);
+                continue;
+            }
+        };
+        let tasks = db.get_organize_tasks(b.id)?;
+        for info in infos {
+            match maintain_torrent(&downloader, &policy, &tasks, &info).await {
+                Ok(true) => {
+                    log::info!(
+                        "seeding finished, removed torrent: {} {}",
+                        b.title,
+                        info.name
+                    );
+                    removed.push(info.hash);
+                }
+                Ok(false) => {}
+                Err(e) => log::error!("seeding error: {} {:?}", info.name, e),
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Apply a policy to one torrent, returning whether it was removed.
+async fn maintain_torrent(
+    downloader: &QbitDownloader,
+    policy: &SeedingPolicy,
+    tasks: &[database::OrganizeTask],
+    info: &InfoResponseItem,
+) -> Result<bool> {
+    if !policy.applied(info) {
+        apply(downloader, std::slice::from_ref(&info.hash), policy).await?;
+    }
+    if policy.remove != Some(true) || info.progress < 1.0 {
+        return Ok(false);
+    }
+    let organized = tasks
+        .iter()
+        .find(|t| t.hash.eq_ignore_ascii_case(&info.hash))
+        .is_none_or(|t| t.done);
+    // qBittorrent pauses torrents that reached their share limits
+    let paused = matches!(info.state, InfoState::PausedUP);
+    if !organized {
+        return Ok(false);
+    }
+    if !paused {
+        // only asked for when there is a time limit to compare it with
+        let minutes = match policy.time {
+            Some(_) => downloader.seeding_time(&info.hash).await? / 60,
+            None => 0,
+        };
+        if !policy.finished(info.ratio, minutes) {
+            return Ok(false);
+        }
+    }
+    downloader
+        .delete(std::slice::from_ref(&info.hash), false)
+        .await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeding_policy() {
+        let policy = "ratio=1.5 time=1440 remove=on"
+            .parse::<SeedingPolicy>()
+            .unwrap();
+        assert_eq!(policy.to_string(), "ratio=1.5 time=1440 remove=on");
+        assert_eq!(
+            "default".parse::<SeedingPolicy>().unwrap(),
+            SeedingPolicy::default()
+        );
+        for invalid in [
+            "ratio=-1",
+            "time=soon",
+            "remove=maybe",
+            "upload",
+            "speed=1",
+            "upload=18446744073709551615",
+        ] {
+            assert!(invalid.parse::<SeedingPolicy>().is_err(), "{}", invalid);
+        }
+        let global = "ratio=2 upload=500 remove=off"
+            .parse::<SeedingPolicy>()
+            .unwrap();
+        let merged = policy.or(&global);
+        assert_eq!(
+            merged.to_string(),
+            "ratio=1.5 time=1440 upload=500 remove=on"
+        );
+
+        assert!(policy.finished(1.5, 0));
+        assert!(policy.finished(0.2, 1440));
+        assert!(!policy.finished(1.0, 60));
+        assert!(SeedingPolicy::default().finished(0.0, 0));
+    }
+}
@@ -0,0 +1,202 @@
+use crate::title_parser::{self, ParseResult};
+use std::fmt;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Release titles with the results `title_parser` should give, one per line:
+/// title, fansub, title_zh, title_en, season and episode separated by tabs.
+pub const CORPUS_PATH: &str = "data/title_corpus.tsv";
+
+type Field = (&'static str, fn(&ParseResult) -> String);
+
+const FIELDS: [Field; 5] = [
+    ("fansub", |r| r.fansub.clone()),
+    ("title_zh", |r| r.title_zh.clone()),
+    ("title_en", |r| r.title_en.clone()),
+    ("season", |r| r.season.to_string()),
+    ("episode", |r| r.episode.to_string()),
+];
+
+#[derive(Error, Debug)]
+pub enum CorpusError {
+    #[error("Invalid corpus line {line}: {reason}")]
+    InvalidLine { line: usize, reason: String },
+}
+
+#[derive(Debug)]
+pub struct Case {
+    pub title: String,
+    pub expected: ParseResult,
+}
+
+/// A field the parser got wrong, with the expected and actual value.
+#[derive(Debug)]
+pub struct Mismatch {
+    pub title: String,
+    pub field: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Accuracy {
+    pub total: usize,
+    /// titles the parser returned an error for
+    pub errors: usize,
+    /// titles with every field right
+    pub exact: usize,
+    pub correct: Vec<(&'static str, usize)>,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl Accuracy {
+    pub fn correct(&self, field: &str) -> usize {
+        self.correct
+            .iter()
+            .find(|(f, _)| *f == field)
+            .map(|(_, n)| *n)
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Accuracy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |n: usize| n as f64 * 100.0 / self.total.max(1) as f64;
+        writeln!(f, "{} titles, {} not parsed", self.total, self.errors)?;
+        for (field, n) in self.correct.iter() {
+            writeln!(
+                f,
+                "{:<10}{:>5}/{:<5}{:>6.1}%",
+                field,
+                n,
+                self.total,
+                percent(*n)
+            )?;
+        }
+        write!(
+            f,
+            "{:<10}{:>5}/{:<5}{:>6.1}%",
+            "all",
+            self.exact,
+            self.total,
+            percent(self.exact)
+        )
+    }
+}
+
+pub fn load(text: &str) -> Result<Vec<Case>, CorpusError> {
+    let mut cases = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: &str| CorpusError::InvalidLine {
+            line: i + 1,
+            reason: reason.to_string(),
+        };
+        let columns = line.split('\t').collect::<Vec<&str>>();
+        let [title, fansub, title_zh, title_en, season, episode] = columns[..] else {
+            return Err(invalid("expected 6 columns"));
+        };
+        cases.push(Case {
+            title: title.to_string(),
+            expected: ParseResult {
+                fansub: fansub.to_string(),
+                title_zh: title_zh.to_string(),
+                title_en: title_en.to_string(),
+                season: season.parse().map_err(|_| invalid("invalid season"))?,
+                episode: episode.parse().map_err(|_| invalid("invalid episode"))?,
+                ..Default::default()
+            },
+        });
+    }
+    Ok(cases)
+}
+
+/// Parse every title and count the right fields.
+pub fn evaluate(cases: &[Case]) -> Accuracy {
+    let mut accuracy = Accuracy {
+        total: cases.len(),
+        correct: FIELDS.iter().map(|(field, _)| (*field, 0)).collect(),
+        ..Default::default()
+    };
+    for case in cases {
+        let result = match title_parser::parse(&case.title) {
+            Ok(result) => result,
+            Err(e) => {
+                accuracy.errors += 1;
+                accuracy.mismatches.push(Mismatch {
+                    title: case.title.clone(),
+                    field: "error",
+                    expected: String::new(),
+                    actual: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let mut exact = true;
+        for (i, (field, value)) in FIELDS.iter().enumerate() {
+            let (expected, actual) = (value(&case.expected), value(&result));
+            if expected == actual {
+                accuracy.correct[i].1 += 1;
+            } else {
+                exact = false;
+                accuracy.mismatches.push(Mismatch {
+                    title: case.title.clone(),
+                    field,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        if exact {
+            accuracy.exact += 1;
+        }
+    }
+    accuracy
+}
+
+/// Parse the corpus over and over for at least `time`, returning how many
+/// titles were parsed and how long it took.
+pub fn bench(cases: &[Case], time: Duration) -> (usize, Duration) {
+    let start = Instant::now();
+    let mut titles = 0;
+    while start.elapsed() < time && !cases.is_empty() {
+        for case in cases {
+            let _ = std::hint::black_box(title_parser::parse(&case.title));
+        }
+        titles += cases.len();
+    }
+    (titles, start.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what the parser achieved when the corpus was added, raise these along
+    // with parser improvements
+    const BASELINE: [(&str, usize); 5] = [
+        ("fansub", 247),
+        ("title_zh", 193),
+        ("title_en", 213),
+        ("season", 247),
+        ("episode", 247),
+    ];
+
+    #[test]
+    fn test_corpus_accuracy() {
+        let cases = load(include_str!("../data/title_corpus.tsv")).unwrap();
+        let accuracy = evaluate(&cases);
+        println!("{}", accuracy);
+        for (field, baseline) in BASELINE {
+            assert!(
+                accuracy.correct(field) >= baseline,
+                "{} regressed: {} < {}",
+                field,
+                accuracy.correct(field),
+                baseline
+            );
+        }
+    }
+}
@@ -1,14 +1,77 @@
 // Edited from EstrellaXD/Auto_Bangumi.git
 
+use crate::error::Result;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
 use thiserror::Error;
 
 const TITLE_PATTERN: &str = r"(.*|\[.*])( -? \d+|\[\d+]|\[\d+.?[vV]\d]|第\d+[话話集]|\[第?\d+[话話集]]|\[\d+.?END]|[Ee][Pp]?\d+)(.*)";
 const EPISODE_PATTERN: &str = r"\d+";
 const PREFIX_PATTERN: &str = r"[^\w\s\u4e00-\u9fff\u3040-\u309f\u30a0-\u30ff-]";
-const FANSUB_PATTERN: &str = r"[\[\]]";
+const VERSION_PATTERN: &str = r"\d[vV](\d)";
+const RESOLUTION_PATTERN: &str = r"(?i)\d{3,4}[x×]\d{3,4}|\d{3,4}p|\b4K\b";
+// each marker is removed from the name, `n` is its number
+const SEASON_PATTERNS: [&str; 6] = [
+    r"\b(?P<marker>S(?P<n>\d{1,2}))",
+    r"(?i)(?P<marker>\bSeason\s*(?P<n>\d{1,2}))\b",
+    r"(?i)(?P<marker>\b(?P<n>\d{1,2})(?:st|nd|rd|th)\s+Season)\b",
+    r"(?P<marker>第(?P<n>[〇零一二两三四五六七八九十百\d]+)[季期])",
+    r"(?P<marker>(?P<n>\d{1,2})期)",
+    // only at the end of a name, `X` and `V` are too often part of one
+    r"\b(?P<marker>(?P<n>II|III|IV|VI|VII|VIII|IX))\s*(?:/|$)",
+];
+const PART_PATTERNS: [&str; 3] = [
+    r"(?i)(?P<marker>\b(?:Part|Cour)\s*(?P<n>\d{1,2}|I{1,3}|IV))\b",
+    r"(?P<marker>第(?P<n>[一二三四五六七八九十\d]+)部分)",
+    r"(?P<marker>(?P<n>[前後后])[編编篇])",
+];
 
-type BoxErr = Box<dyn std::error::Error + Send + Sync>;
+/// The patterns of the parser, compiled once on first use.
+struct Patterns {
+    title: Regex,
+    number: Regex,
+    prefix: Regex,
+    version: Regex,
+    resolution: Regex,
+    seasons: Vec<Regex>,
+    parts: Vec<Regex>,
+    new_season: Regex,
+    episode_range: Regex,
+    region_note: Regex,
+    title_split: Regex,
+    han_prefix: Regex,
+    han: Regex,
+    latin: Regex,
+    kana: Regex,
+}
+
+static PATTERNS: LazyLock<Patterns> = LazyLock::new(|| {
+    let build = |re: &str| Regex::new(re).unwrap();
+    Patterns {
+        title: build(TITLE_PATTERN),
+        number: build(EPISODE_PATTERN),
+        prefix: build(PREFIX_PATTERN),
+        version: build(VERSION_PATTERN),
+        resolution: build(RESOLUTION_PATTERN),
+        seasons: SEASON_PATTERNS.iter().map(|re| build(re)).collect(),
+        parts: PART_PATTERNS.iter().map(|re| build(re)).collect(),
+        new_season: build(r"新番|月?番"),
+        episode_range: build(r"(?:^|[\[\s第])(\d{1,3})[-~～](\d{1,3})(?:[\]\s话話集]|$)"),
+        region_note: build(r"[(（]仅限港澳台地区[）)]"),
+        title_split: build(r"/|\s{2}|-\s{2}"),
+        han_prefix: build(r"^[\u4e00-\u9fa5]{2,}"),
+        han: build(r"[\u4e00-\u9fa5]{2,}"),
+        latin: build(r"[a-zA-Z]{3,}"),
+        kana: build(r"[\u0800-\u4e00]{2,}"),
+    }
+});
+
+// user rules are tried on every title of a feed, keep them compiled
+static RULES: LazyLock<Mutex<HashMap<String, Regex>>> = LazyLock::new(Default::default);
+// more than anyone writes, the cache starts over when it is reached
+const MAX_RULES: usize = 256;
 
 #[derive(Default, Debug, PartialEq)]
 pub struct ParseResult {
@@ -18,6 +81,9 @@ pub struct ParseResult {
     pub season: i8,
     pub episode: i16,
     pub fansub: String,
+    pub version: Option<u8>,
+    /// part of a season split in cours
+    pub part: Option<u8>,
 }
 
 #[derive(Debug, Error)]
@@ -30,27 +96,80 @@ pub enum ParseError {
     InvalidTitle,
     #[error("Failed to parse episode")]
     InvalidEpisode,
+    #[error("Invalid rule: {reason}")]
+    InvalidRule { reason: String },
 }
 
-fn build_regex(re: &str) -> Regex {
-    Regex::new(re).unwrap()
+/// The group in the first brackets of a title.
+pub fn fansub(title: &str) -> Option<String> {
+    parse_fansub(&normalize(title)).filter(|f| !f.is_empty())
 }
 
 fn parse_fansub(title: &str) -> Option<String> {
-    build_regex(FANSUB_PATTERN)
-        .split(title)
-        .nth(1)
-        .map(|s| s.to_string())
+    Tokens::new(title).fansub().map(|s| s.to_string())
+}
+
+/// A title split once into the pieces around its brackets, looked at before
+/// running any pattern on it.
+struct Tokens<'a> {
+    pieces: Vec<&'a str>,
+    /// whether a number follows a space, a bracket, `第`, `E` or `P`, which
+    /// every episode marker of the title pattern starts with
+    marked_number: bool,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(title: &'a str) -> Self {
+        let mut before = None;
+        let mut marked_number = false;
+        for c in title.chars() {
+            if c.is_numeric() && matches!(before, Some(' ' | '[' | '第' | 'E' | 'e' | 'P' | 'p')) {
+                marked_number = true;
+                break;
+            }
+            before = Some(c);
+        }
+        Tokens {
+            pieces: title.split(['[', ']']).collect(),
+            marked_number,
+        }
+    }
+
+    /// The piece in the first brackets.
+    fn fansub(&self) -> Option<&'a str> {
+        self.pieces.get(1).copied()
+    }
+}
+
+/// Remove every `word` with one character on each side, like replacing the
+/// regex `.word.` without compiling it.
+fn remove_wrapped(text: &str, word: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(first) = rest.chars().next() {
+        let after_first = &rest[first.len_utf8()..];
+        let found = after_first.strip_prefix(word).and_then(|after| {
+            let last = after.chars().next()?;
+            Some(&after[last.len_utf8()..])
+        });
+        match found {
+            Some(after) if !word.is_empty() => rest = after,
+            _ => {
+                result.push(first);
+                rest = after_first;
+            }
+        }
+    }
+    result
 }
 
 fn remove_prefix(info: &str, fansub: &str) -> Option<String> {
+    let p = &*PATTERNS;
     let mut raw = info.to_string();
     if !fansub.is_empty() {
-        raw = build_regex(format!(".{}.", fansub).as_str())
-            .replace_all(info, "")
-            .to_string();
+        raw = remove_wrapped(info, fansub);
     }
-    let raw_process = build_regex(PREFIX_PATTERN).replace_all(raw.as_str(), "/");
+    let raw_process = p.prefix.replace_all(raw.as_str(), "/");
     let mut args = raw_process
         .split("/")
         .filter(|&s| !s.is_empty())
@@ -63,60 +182,115 @@ fn remove_prefix(info: &str, fansub: &str) -> Option<String> {
     }
     let mut result = raw.clone();
     for arg in args.iter() {
-        if (build_regex(r"新番|月?番").is_match(arg) && arg.len() <= 5)
-            || build_regex(r"港澳台地区").is_match(arg)
-        {
-            result = build_regex(format!(".{}.", arg).as_str())
-                .replace_all(&result, "")
-                .to_string()
+        if (p.new_season.is_match(arg) && arg.len() <= 5) || arg.contains("港澳台地区") {
+            result = remove_wrapped(&result, arg);
         }
     }
     Some(result.trim().to_string())
 }
 
-fn parse_season(info: &str) -> Option<(String, i8)> {
-    let mut season: i8 = -1;
-    let season_re = build_regex(r"S\d{1,2}|Season \d{1,2}|[第].[季期]");
-    let name_season = build_regex(r"[\[\]]").replace_all(info, " ");
-    let seasons = season_re
-        .find_iter(&name_season)
-        .map(|m| m.as_str())
-        .collect::<Vec<&str>>();
-    if seasons.is_empty() {
-        return Some((name_season.to_string(), 1));
-    }
-    let name = season_re.replace_all(&name_season, "").trim().to_string();
-    for s in seasons.iter() {
-        if build_regex(r"Season|S").is_match(s) {
-            season = build_regex(r"Season|S")
-                .replace_all(s, "")
-                .parse()
-                .unwrap_or(-1);
-            break;
-        } else if build_regex(r"[第 ].*[季期(部分)]|部分").is_match(s) {
-            let season_str = &build_regex(r"[第季期 ]").replace_all(s, "").to_string();
-            season = season_str
-                .parse()
-                .unwrap_or_else(|_| match season_str.as_str() {
-                    "一" => 1,
-                    "二" => 2,
-                    "三" => 3,
-                    "四" => 4,
-                    "五" => 5,
-                    "六" => 6,
-                    "七" => 7,
-                    "八" => 8,
-                    "九" => 9,
-                    "十" => 10,
-                    _ => -1,
-                });
-            break;
+/// Chinese numerals up to the hundreds, e.g. 十二 or 二十一.
+fn chinese_number(s: &str) -> Option<u32> {
+    let (mut total, mut current) = (0, 0);
+    for c in s.chars() {
+        match c {
+            '十' => (total, current) = (total + current.max(1) * 10, 0),
+            '百' => (total, current) = (total + current.max(1) * 100, 0),
+            _ => {
+                current = "〇一二三四五六七八九"
+                    .chars()
+                    .position(|d| d == c)
+                    .or(match c {
+                        '零' => Some(0),
+                        '两' => Some(2),
+                        _ => None,
+                    })? as u32
+            }
+        }
+    }
+    Some(total + current)
+}
+
+fn roman_number(s: &str) -> Option<u32> {
+    let n = ["I", "II", "III", "IV", "V", "VI", "VII", "VIII", "IX", "X"]
+        .iter()
+        .position(|r| *r == s)?;
+    Some(n as u32 + 1)
+}
+
+fn season_number(s: &str) -> Option<u32> {
+    match s {
+        "前" => Some(1),
+        "後" | "后" => Some(2),
+        _ => s
+            .parse()
+            .ok()
+            .or_else(|| chinese_number(s))
+            .or_else(|| roman_number(s)),
+    }
+}
+
+/// Find the markers of `rules` in `name`, removing them, and return the
+/// number of the first one.
+fn take_markers(name: &mut String, rules: &[Regex]) -> Option<Option<u32>> {
+    let mut found = None;
+    for re in rules {
+        let mut spans = Vec::new();
+        for caps in re.captures_iter(name) {
+            let marker = caps.name("marker").unwrap();
+            let n = caps.name("n").and_then(|n| season_number(n.as_str()));
+            if found.is_none_or(|(start, _)| marker.start() < start) {
+                found = Some((marker.start(), n));
+            }
+            spans.push(marker.range());
+        }
+        for span in spans.into_iter().rev() {
+            name.replace_range(span, "");
         }
     }
-    if name.trim().is_empty() || season == -1 {
+    found.map(|(_, n)| n)
+}
+
+/// How many episodes a title is for: the size of a range like `01-12` in
+/// batch titles, or one.
+pub fn episode_count(title: &str) -> usize {
+    PATTERNS
+        .episode_range
+        .captures(title)
+        .and_then(|caps| {
+            let from = caps[1].parse::<usize>().ok()?;
+            let to = caps[2].parse::<usize>().ok()?;
+            Some(to.checked_sub(from)? + 1)
+        })
+        .unwrap_or(1)
+}
+
+/// Whether a name has a season marker like `S02` or `第二季`.
+pub fn has_season(name: &str) -> bool {
+    PATTERNS.seasons.iter().any(|re| re.is_match(name))
+}
+
+/// The name without season and part markers, the season and the part of a
+/// split cour. Parts such as `Part 2` or `後編` do not change the season.
+fn parse_season(info: &str) -> Option<(String, i8, Option<u8>)> {
+    let p = &*PATTERNS;
+    let mut name = info.replace(['[', ']'], " ");
+    // `Part II` is no season, take parts out before looking for those
+    let part = take_markers(&mut name, &p.parts);
+    let season = take_markers(&mut name, &p.seasons);
+    let part = match part {
+        Some(n) => Some(u8::try_from(n?).ok()?),
+        None => None,
+    };
+    let name = name.trim().to_string();
+    let season = match season {
+        Some(n) => i8::try_from(n?).ok()?,
+        None => return Some((name, 1, part)),
+    };
+    if name.is_empty() {
         return None;
     }
-    Some((name, season))
+    Some((name, season, part))
 }
 
 fn parse_title(title: &str) -> (Option<String>, Option<String>, Option<String>) {
@@ -124,16 +298,18 @@ fn parse_title(title: &str) -> (Option<String>, Option<String>, Option<String>)
     let mut title_en = None;
     let mut title_jp = None;
 
-    let title = build_regex(r"[(（]仅限港澳台地区[）)]").replace_all(title, "");
-    let mut split: Vec<&str> = build_regex(r"/|\s{2}|-\s{2}")
+    let p = &*PATTERNS;
+    let title = p.region_note.replace_all(title, "");
+    let mut split: Vec<&str> = p
+        .title_split
         .split(&title)
         .filter(|&s| !s.is_empty())
         .collect();
     if split.len() == 1 {
-        if build_regex(r"_{1}").is_match(&title) {
-            split = build_regex(r"_").split(&title).collect();
-        } else if build_regex(r" - {1}").is_match(&title) {
-            split = build_regex("-").split(&title).collect();
+        if title.contains('_') {
+            split = title.split('_').collect();
+        } else if title.contains(" - ") {
+            split = title.split('-').collect();
         }
     }
     let mut split_result = split.clone();
@@ -141,7 +317,7 @@ fn parse_title(title: &str) -> (Option<String>, Option<String>, Option<String>)
         let mut split_space = split[0].split_whitespace().collect::<Vec<&str>>();
         split_space = vec![split_space[0], split_space.last().unwrap()];
         for s in split_space.clone().iter() {
-            if build_regex(r"^[\u4e00-\u9fa5]{2,}").is_match(s) {
+            if p.han_prefix.is_match(s) {
                 split_space.retain(|x| x != s);
                 split_result.clear();
                 split_result.push(s);
@@ -151,21 +327,27 @@ fn parse_title(title: &str) -> (Option<String>, Option<String>, Option<String>)
         }
     }
     for s in split.iter() {
-        if build_regex(r"[\u4e00-\u9fa5]{2,}").is_match(s) {
+        if p.han.is_match(s) {
             title_zh = Some(s.trim().to_string());
-        } else if build_regex(r"[a-zA-Z]{3,}").is_match(s) {
+        } else if p.latin.is_match(s) {
             title_en = Some(s.trim().to_string());
-        } else if build_regex(r"[\u0800-\u4e00]{2,}").is_match(s) {
+        } else if p.kana.is_match(s) {
             title_jp = Some(s.trim().to_string());
         }
     }
     (title_zh, title_en, title_jp)
 }
 
-pub fn parse(title: &str) -> Result<ParseResult, BoxErr> {
-    let title = title.trim().replace("【", "[").replace("】", "]");
-    let fansub = parse_fansub(&title).unwrap_or_default();
-    let info_split = build_regex(TITLE_PATTERN)
+pub fn parse(title: &str) -> Result<ParseResult> {
+    let title = normalize(title);
+    let tokens = Tokens::new(&title);
+    let fansub = tokens.fansub().unwrap_or_default().to_string();
+    // skip the expensive pattern for titles it cannot match
+    if !tokens.marked_number {
+        return Err(ParseError::InvalidInput.into());
+    }
+    let info_split = PATTERNS
+        .title
         .captures(&title)
         .ok_or(ParseError::InvalidInput)?;
     let infos: Vec<&str> = (1..=3)
@@ -174,20 +356,22 @@ pub fn parse(title: &str) -> Result<ParseResult, BoxErr> {
     let (season_info, episode_info, _) = (infos[0], infos[1], infos[2]);
     let raw_season_info = remove_prefix(season_info, &fansub).unwrap_or_default();
     if raw_season_info.trim().is_empty() {
-        return Err(Box::new(ParseError::InvalidInput));
+        return Err(ParseError::InvalidInput.into());
     }
-    let (raw_name, season) = parse_season(&raw_season_info).ok_or(ParseError::InvalidSeason)?;
+    let (raw_name, season, part) =
+        parse_season(&raw_season_info).ok_or(ParseError::InvalidSeason)?;
     let (title_zh, title_en, title_jp) = parse_title(&raw_name);
     if title_zh.is_none() && title_en.is_none() && title_jp.is_none() {
-        return Err(Box::new(ParseError::InvalidTitle));
+        return Err(ParseError::InvalidTitle.into());
     }
-    let raw_episode = build_regex(EPISODE_PATTERN)
-        .find(episode_info)
-        .unwrap()
-        .as_str();
+    let raw_episode = PATTERNS.number.find(episode_info).unwrap().as_str();
     let episode = raw_episode
         .parse::<i16>()
         .map_err(|_| ParseError::InvalidEpisode)?;
+    let version = PATTERNS
+        .version
+        .captures(episode_info)
+        .and_then(|c| c[1].parse().ok());
     Ok(ParseResult {
         title_zh: title_zh.unwrap_or_default(),
         title_en: title_en.unwrap_or_default(),
@@ -195,9 +379,152 @@ pub fn parse(title: &str) -> Result<ParseResult, BoxErr> {
         fansub,
         season,
         episode,
+        version,
+        part,
     })
 }
 
+fn compile_rule(pattern: &str) -> Result<Regex> {
+    if let Some(re) = RULES.lock().unwrap().get(pattern) {
+        return Ok(re.clone());
+    }
+    let re = Regex::new(pattern).map_err(|e| ParseError::InvalidRule {
+        reason: e.to_string(),
+    })?;
+    if !re.capture_names().any(|n| n == Some("episode")) {
+        return Err(ParseError::InvalidRule {
+            reason: "missing group (?P<episode>...)".to_string(),
+        }
+        .into());
+    }
+    let mut rules = RULES.lock().unwrap();
+    if rules.len() >= MAX_RULES {
+        rules.clear();
+    }
+    rules.insert(pattern.to_string(), re.clone());
+    Ok(re)
+}
+
+/// Drop a removed user rule from the cache.
+pub fn forget_rule(pattern: &str) {
+    RULES.lock().unwrap().remove(pattern);
+}
+
+/// Check that a user rule compiles and has an `episode` group.
+pub fn validate_rule(pattern: &str) -> Result<()> {
+    compile_rule(pattern).map(|_| ())
+}
+
+/// Parse with a user rule, a regex with the named group `episode` and
+/// optionally `title`, `season` and `version`. `None` if it does not match.
+pub fn parse_with_rule(pattern: &str, title: &str) -> Result<Option<ParseResult>> {
+    let re = compile_rule(pattern)?;
+    let title = normalize(title);
+    let caps = match re.captures(&title) {
+        Some(caps) => caps,
+        None => return Ok(None),
+    };
+    let group = |name| caps.name(name).map(|m| m.as_str().trim());
+    let episode = group("episode")
+        .and_then(|e| e.parse().ok())
+        .ok_or(ParseError::InvalidEpisode)?;
+    let season = match group("season") {
+        Some(season) => season.parse().map_err(|_| ParseError::InvalidSeason)?,
+        None => 1,
+    };
+    let (title_zh, title_en, title_jp) = match group("title") {
+        Some(name) if !name.is_empty() => parse_title(name),
+        _ => (None, None, None),
+    };
+    Ok(Some(ParseResult {
+        title_zh: title_zh.unwrap_or_default(),
+        title_en: title_en.unwrap_or_default(),
+        title_jp: title_jp.unwrap_or_default(),
+        fansub: parse_fansub(&title).unwrap_or_default(),
+        season,
+        episode,
+        version: group("version").and_then(|v| v.parse().ok()),
+        part: None,
+    }))
+}
+
+/// A season and episode a user gave for a title the parser could not handle,
+/// applied to later titles of the same shape, i.e. equal but for numbers.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LearnedRule {
+    pub fansub: String,
+    pub shape: String,
+    pub season: i8,
+    /// which number of the title is the episode
+    pub number_index: usize,
+}
+
+impl LearnedRule {
+    /// A rule if `episode` appears as a number in `title`.
+    pub fn learn(title: &str, season: i8, episode: i16) -> Option<Self> {
+        let title = normalize(title);
+        let number_index = numbers(&title)
+            .iter()
+            .position(|n| n.parse::<i16>().ok() == Some(episode))?;
+        Some(LearnedRule {
+            fansub: parse_fansub(&title).unwrap_or_default(),
+            shape: title_shape(&title),
+            season,
+            number_index,
+        })
+    }
+    pub fn apply(&self, title: &str) -> Option<ParseResult> {
+        let title = normalize(title);
+        if title_shape(&title) != self.shape {
+            return None;
+        }
+        let episode = numbers(&title).get(self.number_index)?.parse().ok()?;
+        Some(ParseResult {
+            fansub: self.fansub.clone(),
+            season: self.season,
+            episode,
+            ..Default::default()
+        })
+    }
+}
+
+fn normalize(title: &str) -> String {
+    title.trim().replace("【", "[").replace("】", "]")
+}
+
+fn numbers(title: &str) -> Vec<&str> {
+    PATTERNS
+        .number
+        .find_iter(title)
+        .map(|m| m.as_str())
+        .collect()
+}
+
+fn title_shape(title: &str) -> String {
+    PATTERNS.number.replace_all(title, "#").to_string()
+}
+
+/// Numbers of a title that could be its episode, for asking the user.
+pub fn episode_candidates(title: &str) -> Vec<i16> {
+    let title = normalize(title);
+    let title = PATTERNS.resolution.replace_all(&title, "");
+    let mut candidates = Vec::new();
+    for n in numbers(&title) {
+        match n.parse::<i16>() {
+            Ok(n) if n < 1000 && !candidates.contains(&n) => candidates.push(n),
+            _ => {}
+        }
+    }
+    candidates
+}
+
+pub fn parse_resolution(title: &str) -> Option<String> {
+    PATTERNS
+        .resolution
+        .find(title)
+        .map(|m| m.as_str().to_uppercase().replace('×', "X"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -215,7 +542,106 @@ mod tests {
                 fansub: "ANi".to_string(),
                 season: 2,
                 episode: 13,
+                version: None,
+                part: None,
             }
         );
     }
+
+    #[test]
+    fn test_parse_season() {
+        assert_eq!(chinese_number("十二"), Some(12));
+        assert_eq!(chinese_number("二十一"), Some(21));
+        assert_eq!(chinese_number("两"), Some(2));
+        assert_eq!(chinese_number("第"), None);
+        let season = parse_season;
+        assert_eq!(
+            season("Mushoku Tensei II"),
+            Some(("Mushoku Tensei".to_string(), 2, None))
+        );
+        assert_eq!(
+            season("Oshi no Ko 2nd Season"),
+            Some(("Oshi no Ko".to_string(), 2, None))
+        );
+        assert_eq!(
+            season("Mahoutsukai no Yome Part II"),
+            Some(("Mahoutsukai no Yome".to_string(), 1, Some(2)))
+        );
+        assert_eq!(season("斗罗大陆 第十二季").map(|s| s.1), Some(12));
+        assert_eq!(season("摇曳露营 2期").map(|s| s.1), Some(2));
+        assert!(has_season("Oshi no Ko S02E03"));
+        assert_eq!(episode_count("[LoliHouse] Frieren [01-28 Fin][1080p]"), 28);
+        assert_eq!(episode_count("[桜都字幕组] 葬送的芙莉莲 第01~12话"), 12);
+        assert_eq!(episode_count("[SubsPlease] Frieren - 06 (1080p)"), 1);
+        assert!(!has_season("[SubsPlease] Oshi no Ko - 03 (1080p)"));
+        assert_eq!(
+            season("魔法使的新娘 第二季 後編").map(|s| (s.1, s.2)),
+            Some((2, Some(2)))
+        );
+        assert_eq!(season("SPY X FAMILY").map(|s| s.1), Some(1));
+        assert_eq!(season("第二季"), None);
+    }
+
+    #[test]
+    fn test_remove_wrapped() {
+        assert_eq!(remove_wrapped("[ANi] 葬送的芙莉莲", "ANi"), " 葬送的芙莉莲");
+        assert_eq!(
+            remove_wrapped("ANi 葬送的芙莉莲", "ANi"),
+            "ANi 葬送的芙莉莲"
+        );
+        assert_eq!(remove_wrapped("[c.c] x [c.c]", "c.c"), " x ");
+        assert_eq!(remove_wrapped("abc", ""), "abc");
+    }
+
+    #[test]
+    fn test_parse_with_rule() {
+        let rule =
+            r"\[(?P<title>[^\]]+)\]\[S(?P<season>\d+)\]\[(?P<episode>\d+)(v(?P<version>\d))?\]";
+        let title = "[SweetSub][Mato Seihei no Slave][S2][05v2][WebRip][1080P]";
+        let result = parse_with_rule(rule, title).unwrap().unwrap();
+        assert_eq!(result.title_en, "Mato Seihei no Slave");
+        assert_eq!((result.season, result.episode), (2, 5));
+        assert_eq!(result.version, Some(2));
+        assert_eq!(result.fansub, "SweetSub");
+        assert_eq!(
+            parse_with_rule(rule, "[ANi] 葬送的芙莉莲 - 20").unwrap(),
+            None
+        );
+        assert!(validate_rule(r"(?P<title>.*) - \d+").is_err());
+        assert!(validate_rule(r"(?P<episode>\d+").is_err());
+    }
+
+    #[test]
+    fn test_tokens() {
+        let tokens = Tokens::new("[ANi] 葬送的芙莉莲 - 20 [1080P]");
+        assert_eq!(tokens.fansub(), Some("ANi"));
+        assert!(tokens.marked_number);
+        assert!(Tokens::new("[LoliHouse] Frieren EP06").marked_number);
+        assert!(!Tokens::new("[ANi] Frieren2024 (x265)").marked_number);
+        assert!(parse("[ANi] Frieren2024 (x265)").is_err());
+    }
+
+    #[test]
+    fn test_learned_rule() {
+        let title = "[Nekomoe kissaten] Kusuriya no Hitorigoto 2 Dai 05 Wa [WebRip 1080p]";
+        assert_eq!(episode_candidates(title), vec![2, 5]);
+        let rule = LearnedRule::learn(title, 2, 5).unwrap();
+        let next = "[Nekomoe kissaten] Kusuriya no Hitorigoto 2 Dai 12 Wa [WebRip 1080p]";
+        let result = rule.apply(next).unwrap();
+        assert_eq!((result.season, result.episode), (2, 12));
+        assert_eq!(result.fansub, "Nekomoe kissaten");
+        assert!(rule
+            .apply("[ANi] Kusuriya no Hitorigoto - 12 [1080P]")
+            .is_none());
+        assert!(LearnedRule::learn(title, 2, 7).is_none());
+    }
+
+    #[test]
+    fn test_parse_resolution() {
+        let title = "[ANi] 我内心的糟糕念头 第二季 - 13 [1080P][Baha][WEB-DL][AAC AVC][CHT][MP4]";
+        assert_eq!(parse_resolution(title), Some("1080P".to_string()));
+        let title = "[LoliHouse] 葬送的芙莉莲 - 20 [WebRip 1920x1080 HEVC-10bit AAC]";
+        assert_eq!(parse_resolution(title), Some("1920X1080".to_string()));
+        assert_eq!(parse_resolution("[ANi] 葬送的芙莉莲 - 20"), None);
+    }
 }
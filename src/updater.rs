@@ -0,0 +1,1261 @@
+use crate::error::Result;
+use crate::i18n::{fill, Locale, Msg};
+use crate::{bot, database, downloader, mikan, organizer, seeding, title_parser, utils};
+use lava_torrent::torrent::v1::Torrent;
+use std::sync::Arc;
+use teloxide::payloads::{SendMessageSetters, SendPhotoSetters};
+use teloxide::requests::Requester;
+use teloxide::types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, Recipient};
+use thiserror::Error;
+use tokio::sync::{Mutex, Semaphore};
+
+const MIKAN_BANGUMI_URL: &str = "https://mikanani.me/Home/Bangumi";
+// telegram limits photo captions to 1024 characters
+const CAPTION_LIMIT: usize = 1024;
+// failed downloads wait 5 minutes, doubling up to 12 hours
+const RETRY_DELAY: i64 = 5 * 60;
+const RETRY_MAX_DELAY: i64 = 12 * 3600;
+
+/// An episode handed to the downloader.
+#[derive(Debug, Clone)]
+pub struct Queued {
+    pub save_name: String,
+    pub title: String,
+    pub link: String,
+    pub season: i8,
+    pub episode: i16,
+    pub fansub: String,
+    pub resolution: Option<String>,
+    pub size: Option<u64>,
+    /// season and episode of the video in the torrent when they differ from
+    /// the ones in the title
+    pub file_mismatch: Option<(i8, i16)>,
+    /// doubts about the torrent that were not reason enough to reject it
+    pub issues: Vec<TorrentIssue>,
+}
+
+/// Why an RSS item was not queued.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkipReason {
+    Filtered(String),
+    NoTorrent,
+    Downloaded,
+    /// already in the feed when subscribing with `new`
+    Ignored,
+    /// imported from an existing library
+    Imported,
+    BeforeFrom(i16),
+    Unparseable,
+    /// older than the newest item of the previous check
+    Seen,
+    /// in the retry queue, which downloads it when due
+    Retrying,
+    GaveUp,
+    Rejected(TorrentIssue),
+}
+
+impl SkipReason {
+    pub fn message(&self, locale: Locale) -> String {
+        match self {
+            SkipReason::Filtered(word) => fill(locale.text(Msg::SkipFiltered), &[word]),
+            SkipReason::NoTorrent => locale.text(Msg::SkipNoTorrent).to_string(),
+            SkipReason::Downloaded => locale.text(Msg::SkipDownloaded).to_string(),
+            SkipReason::Ignored => locale.text(Msg::SkipIgnored).to_string(),
+            SkipReason::Imported => locale.text(Msg::SkipImported).to_string(),
+            SkipReason::BeforeFrom(from) => fill(locale.text(Msg::SkipBeforeFrom), &[from]),
+            SkipReason::Unparseable => locale.text(Msg::SkipUnparseable).to_string(),
+            SkipReason::Seen => locale.text(Msg::SkipSeen).to_string(),
+            SkipReason::Retrying => locale.text(Msg::SkipRetrying).to_string(),
+            SkipReason::GaveUp => locale.text(Msg::SkipGaveUp).to_string(),
+            SkipReason::Rejected(issue) => {
+                fill(locale.text(Msg::SkipRejected), &[&issue.message(locale)])
+            }
+        }
+    }
+}
+
+/// Something suspicious about a torrent, found before downloading it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TorrentIssue {
+    Size {
+        size: u64,
+        min: u64,
+        max: u64,
+    },
+    /// an executable or archive, never part of a release
+    ForbiddenFile(String),
+    /// episode sized videos in a torrent for another number of episodes
+    VideoCount {
+        videos: usize,
+        episodes: usize,
+    },
+}
+
+impl TorrentIssue {
+    pub fn message(&self, locale: Locale) -> String {
+        match self {
+            TorrentIssue::Size { size, min, max } => fill(
+                locale.text(Msg::TorrentSize),
+                &[
+                    &utils::human_size(*size),
+                    &utils::human_size(*min),
+                    &utils::human_size(*max),
+                ],
+            ),
+            TorrentIssue::ForbiddenFile(name) => {
+                fill(locale.text(Msg::TorrentForbiddenFile), &[name])
+            }
+            TorrentIssue::VideoCount { videos, episodes } => {
+                fill(locale.text(Msg::TorrentVideoCount), &[videos, episodes])
+            }
+        }
+    }
+
+    /// Whether the torrent is not downloaded, or only flagged.
+    pub fn rejects(&self) -> bool {
+        !matches!(self, TorrentIssue::VideoCount { .. })
+    }
+}
+
+/// What one refresh of a subscription found.
+#[derive(Debug, Default)]
+pub struct UpdateReport {
+    pub found: usize,
+    pub skipped: Vec<(String, SkipReason)>,
+    pub queued: Vec<Queued>,
+    /// downloads that failed in this refresh
+    pub failed: Vec<database::RetryTask>,
+    /// items put into quarantine in this refresh
+    pub quarantined: Vec<database::QuarantineItem>,
+    /// the feed was not modified since the previous check
+    pub unchanged: bool,
+    /// titles whose torrent did not pass [`inspect_torrent`]
+    pub rejected: Vec<(String, TorrentIssue)>,
+}
+
+#[derive(Error, Debug)]
+pub enum UpdateError {
+    #[error("A check is already running")]
+    AlreadyRunning,
+    #[error("Torrent rejected: {issue:?}")]
+    Rejected { issue: TorrentIssue },
+}
+
+// the scheduled loop and `/check` must not download the same items twice
+static CHECK_LOCK: Mutex<()> = Mutex::const_new(());
+
+fn filter_reason(b: &database::Bangumi, ep: &mikan::RssEpisode) -> Option<SkipReason> {
+    if let Some(word) = b
+        .not_contains
+        .iter()
+        .find(|s| ep.title.contains(s.as_str()))
+    {
+        return Some(SkipReason::Filtered(word.clone()));
+    }
+    if ep.torrent_hash.is_empty() {
+        return Some(SkipReason::NoTorrent);
+    }
+    if b.downloaded.contains(&ep.torrent_hash) {
+        return Some(SkipReason::Downloaded);
+    }
+    None
+}
+
+/// Seconds to wait before trying a download again after `attempts` failures.
+pub fn retry_delay(attempts: u32) -> i64 {
+    let doublings = attempts.saturating_sub(1).min(16);
+    (RETRY_DELAY << doublings).min(RETRY_MAX_DELAY)
+}
+
+fn record_failure(
+    db: &database::Client,
+    cfg: &bot::Config,
+    mut task: database::RetryTask,
+    e: &crate::error::Error,
+) -> Result<database::RetryTask> {
+    log::error!("download error: {} {:?}", task.title, e);
+    task.attempts += 1;
+    task.next_try = chrono::Utc::now().timestamp() + retry_delay(task.attempts);
+    task.last_error = e.to_string();
+    task.gave_up = task.attempts >= cfg.retry_attempts;
+    db.save_retry_task(&task)?;
+    Ok(task)
+}
+
+/// What recognized a title.
+#[derive(Debug, Clone)]
+pub enum ParsedBy {
+    Rule(database::ParseRule),
+    BuiltIn,
+    Learned,
+}
+
+/// Parse a title with the user rules of the bangumi and of its fansub first,
+/// then the built-in parser, then the rules learned for them.
+pub fn explain_parse(
+    b: Option<&database::Bangumi>,
+    rules: &[database::ParseRule],
+    title: &str,
+) -> Result<(title_parser::ParseResult, ParsedBy)> {
+    let fansub = title_parser::fansub(title).map(database::RuleScope::Fansub);
+    let bangumi = b.map(|b| database::RuleScope::Bangumi(b.id));
+    let scoped = [bangumi, fansub]
+        .into_iter()
+        .flatten()
+        .flat_map(|scope| rules.iter().filter(move |r| r.scope == scope));
+    let (learned, scoped): (Vec<_>, Vec<_>) = scoped.partition(|r| r.learned.is_some());
+    for rule in scoped {
+        match title_parser::parse_with_rule(&rule.pattern, title) {
+            Ok(Some(result)) => return Ok((result, ParsedBy::Rule(rule.clone()))),
+            Ok(None) => {}
+            Err(e) => log::warn!("parse rule {} error: {:?}", rule.id, e),
+        }
+    }
+    let error = match title_parser::parse(title) {
+        Ok(result) => return Ok((result, ParsedBy::BuiltIn)),
+        Err(e) => e,
+    };
+    learned
+        .iter()
+        .filter_map(|r| r.learned.as_ref())
+        .find_map(|r| r.apply(title))
+        .map(|result| (result, ParsedBy::Learned))
+        .ok_or(error)
+}
+
+pub fn parse_episode(
+    b: &database::Bangumi,
+    rules: &[database::ParseRule],
+    title: &str,
+) -> Result<title_parser::ParseResult> {
+    explain_parse(Some(b), rules, title).map(|(result, _)| result)
+}
+
+/// The names of the videos in a torrent, largest first.
+pub fn video_files(torrent: &Torrent) -> Vec<String> {
+    let mut files = torrent_files(torrent);
+    files.retain(|(_, name)| utils::is_video(name));
+    files.sort_by_key(|(length, _)| std::cmp::Reverse(*length));
+    files.into_iter().map(|(_, name)| name).collect()
+}
+
+fn torrent_files(torrent: &Torrent) -> Vec<(i64, String)> {
+    match torrent.files.as_ref() {
+        Some(files) => files
+            .iter()
+            .filter_map(|f| {
+                let name = f.path.file_name()?.to_string_lossy().to_string();
+                Some((f.length, name))
+            })
+            .collect(),
+        None => vec![(torrent.length, torrent.name.clone())],
+    }
+}
+
+/// The names of the videos in a torrent at least half the size of the
+/// largest, smaller ones are taken for extras like openings.
+fn episode_files(torrent: &Torrent) -> Vec<String> {
+    let mut videos = torrent_files(torrent);
+    videos.retain(|(_, name)| utils::is_video(name));
+    let largest = videos.iter().map(|(length, _)| *length).max();
+    videos.retain(|(length, _)| largest.is_some_and(|l| *length * 2 >= l));
+    videos.into_iter().map(|(_, name)| name).collect()
+}
+
+/// Check a torrent for `episodes` episodes before downloading it.
+pub fn inspect_torrent(
+    torrent: &Torrent,
+    size: &std::ops::RangeInclusive<u64>,
+    episodes: usize,
+) -> Vec<TorrentIssue> {
+    let mut issues = Vec::new();
+    let length = u64::try_from(torrent.length).unwrap_or_default();
+    if !size.contains(&length) {
+        issues.push(TorrentIssue::Size {
+            size: length,
+            min: *size.start(),
+            max: *size.end(),
+        });
+    }
+    let files = torrent_files(torrent);
+    if let Some((_, name)) = files.iter().find(|(_, name)| {
+        matches!(
+            utils::file_extension(name)
+                .map(|e| e.to_lowercase())
+                .as_deref(),
+            Some("exe" | "scr" | "rar")
+        )
+    }) {
+        issues.push(TorrentIssue::ForbiddenFile(name.clone()));
+    }
+    let videos = episode_files(torrent).len();
+    if videos != episodes {
+        issues.push(TorrentIssue::VideoCount { videos, episodes });
+    }
+    issues
+}
+
+/// Parse the name of the main video in a torrent, which is often better
+/// formed than the RSS title.
+fn parse_files(
+    b: &database::Bangumi,
+    rules: &[database::ParseRule],
+    torrent: &Torrent,
+) -> Option<title_parser::ParseResult> {
+    let name = video_files(torrent).into_iter().next()?;
+    parse_episode(b, rules, file_stem(&name)).ok()
+}
+
+fn file_stem(name: &str) -> &str {
+    name.rsplit_once('.').map_or(name, |(stem, _)| stem)
+}
+
+/// The season and episode of the video in a torrent when they differ from
+/// `ep_info`. File names often leave out the season, so it is only compared
+/// when the name has one, and torrents with several episodes are not checked.
+fn file_mismatch(
+    b: &database::Bangumi,
+    rules: &[database::ParseRule],
+    torrent: &Torrent,
+    ep_info: &title_parser::ParseResult,
+) -> Option<(i8, i16)> {
+    let files = episode_files(torrent);
+    let [name] = files.as_slice() else {
+        return None;
+    };
+    let stem = file_stem(name);
+    let file = parse_episode(b, rules, stem).ok()?;
+    let season = if title_parser::has_season(stem) {
+        file.season
+    } else {
+        ep_info.season
+    };
+    Some((season, file.episode)).filter(|file| *file != (ep_info.season, ep_info.episode))
+}
+
+fn has_episode(b: &database::Bangumi, season: i8, episode: i16) -> bool {
+    b.episodes
+        .iter()
+        .any(|e| e.season == season && e.episode == episode)
+}
+
+/// Refresh the given subscriptions, or every enabled one, unless a check is
+/// already running. Subscriptions asked for by id are refreshed even when
+/// disabled, ids removed in the meantime are skipped.
+pub async fn check(
+    ids: Option<Vec<u32>>,
+    db: Arc<database::Client>,
+    cfg: Arc<bot::Config>,
+    tg: Arc<teloxide::Bot>,
+) -> Result<Vec<(database::Bangumi, Result<UpdateReport>)>> {
+    let _guard = CHECK_LOCK
+        .try_lock()
+        .map_err(|_| UpdateError::AlreadyRunning)?;
+    let bangumis = match ids {
+        Some(ids) => {
+            let mut bangumis = Vec::new();
+            for id in ids {
+                match db.get_bangumi(id)? {
+                    Some(b) => bangumis.push(b),
+                    None => log::warn!("check skipped removed bangumi: {}", id),
+                }
+            }
+            bangumis
+        }
+        None => db.get_rss_bangumi()?,
+    };
+    let semaphore = Arc::new(Semaphore::new(cfg.concurrency.max(1)));
+    let handles = bangumis
+        .into_iter()
+        .map(|b| {
+            let (semaphore, db, cfg, tg) = (semaphore.clone(), db.clone(), cfg.clone(), tg.clone());
+            tokio::spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                log::info!("update rss: {} - {}", b.id, b.title);
+                let result = update_rss(b.clone(), db, cfg, tg).await;
+                if let Err(e) = result.as_ref() {
+                    log::error!("update rss error: {:?}", e);
+                }
+                (b, result)
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut results = Vec::new();
+    for handle in handles {
+        match handle.await {
+            Ok(result) => results.push(result),
+            Err(e) => log::error!("update task error: {:?}", e),
+        }
+    }
+    Ok(results)
+}
+
+pub async fn update_rss(
+    b: database::Bangumi,
+    db: Arc<database::Client>,
+    cfg: Arc<bot::Config>,
+    tg: Arc<teloxide::Bot>,
+) -> Result<UpdateReport> {
+    let retries = db.get_retry_tasks(Some(b.id))?;
+    let quarantine = db.get_quarantine(Some(b.id))?;
+    let fetched = match mikan::MikanRss::from_url(&b.rss_url).set_proxy(cfg.proxy.clone()) {
+        Ok(rss) => rss.fetch_if_changed(&b.feed).await,
+        Err(e) => Err(e.into()),
+    };
+    // the retry queue does not need the feed, work it off even when it is down
+    let (rss, mut feed, fetch_error) = match fetched {
+        Ok((rss, feed)) => (rss, feed, None),
+        Err(e) => (None, b.feed.clone(), Some(e)),
+    };
+    let mut report = UpdateReport::default();
+    match rss {
+        Some(rss) => {
+            report.found = rss.items.len();
+            let seen = b.feed.seen_from(&rss.items);
+            let items = &rss.items[..seen];
+            queue_new(&b, items, &retries, &quarantine, &db, &cfg, &mut report).await?;
+            for ep in rss.items[seen..].iter() {
+                report.skipped.push((ep.title.clone(), SkipReason::Seen));
+            }
+            // failed items are in the retry queue, no need to see them again
+            feed.mark_seen(&rss.items);
+        }
+        None => report.unchanged = true,
+    }
+    if feed != b.feed {
+        db.set_bangumi_feed(b.id, &feed)?;
+    }
+    retry_due(&b, retries, &db, &cfg, &mut report).await?;
+    if !report.queued.is_empty() {
+        notify_episodes(&tg, &db, &cfg, &b, &report.queued).await?;
+    }
+    notify_gave_up(&tg, &db, &cfg, &b, &report.failed).await?;
+    notify_rejected(&tg, &db, &cfg, &b, &report.rejected).await?;
+    notify_quarantined(&tg, &db, &cfg, &b, &report.quarantined).await?;
+    match fetch_error {
+        Some(e) => Err(e),
+        None => Ok(report),
+    }
+}
+
+/// Download the failed items of a bangumi whose next attempt is due.
+async fn retry_due(
+    b: &database::Bangumi,
+    retries: Vec<database::RetryTask>,
+    db: &database::Client,
+    cfg: &bot::Config,
+    report: &mut UpdateReport,
+) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    let rules = db.get_parse_rules()?;
+    for task in retries
+        .into_iter()
+        .filter(|t| !t.gave_up && t.next_try <= now)
+    {
+        let ep = mikan::RssEpisode {
+            title: task.title.clone(),
+            link: task.link.clone(),
+            description: String::new(),
+            torrent_url: task.torrent_url.clone(),
+            torrent_hash: task.hash.clone(),
+            guid: task.hash.clone(),
+            pub_date: None,
+        };
+        log::info!("retrying download: {:?}", ep);
+        let result = match parse_episode(b, &rules, &ep.title) {
+            Ok(ep_info) => download_fetched(b, &ep, None, &ep_info, &rules, db, cfg).await,
+            Err(e) => match fetch_torrent(&ep, cfg).await {
+                Ok(torrent) => match parse_files(b, &rules, &torrent.1) {
+                    Some(ep_info) => {
+                        download_fetched(b, &ep, Some(torrent), &ep_info, &rules, db, cfg).await
+                    }
+                    None => Err(e),
+                },
+                Err(_) => Err(e),
+            },
+        };
+        match result {
+            Ok(queued) => {
+                db.delete_retry_task(&task.hash)?;
+                report.queued.push(queued);
+            }
+            Err(crate::error::Error::Update(UpdateError::Rejected { issue })) => {
+                log::warn!("torrent rejected: {} {:?}", task.title, issue);
+                db.delete_retry_task(&task.hash)?;
+                report.rejected.push((task.title.clone(), issue));
+            }
+            Err(e) => report.failed.push(record_failure(db, cfg, task, &e)?),
+        }
+    }
+    Ok(())
+}
+
+async fn queue_new(
+    b: &database::Bangumi,
+    items: &[mikan::RssEpisode],
+    retries: &[database::RetryTask],
+    quarantine: &[database::QuarantineItem],
+    db: &database::Client,
+    cfg: &bot::Config,
+    report: &mut UpdateReport,
+) -> Result<()> {
+    let rules = db.get_parse_rules()?;
+    for ep in items.iter().rev() {
+        let mut reason = filter_reason(b, ep);
+        if reason.is_none() && b.ignored.contains(&ep.torrent_hash) {
+            reason = Some(SkipReason::Ignored);
+        }
+        if reason.is_none() {
+            reason = retries
+                .iter()
+                .find(|t| t.hash == ep.torrent_hash)
+                .map(|t| match t.gave_up {
+                    true => SkipReason::GaveUp,
+                    false => SkipReason::Retrying,
+                });
+        }
+        if let Some(reason) = reason {
+            report.skipped.push((ep.title.clone(), reason));
+            continue;
+        }
+        let mut fetched = None;
+        let ep_info = match parse_episode(b, &rules, &ep.title) {
+            Ok(info) => info,
+            Err(e) => {
+                log::warn!("cannot parse {}: {:?}", ep.title, e);
+                let from_files = match fetch_torrent(ep, cfg).await {
+                    Ok(torrent) => parse_files(b, &rules, &torrent.1).map(|info| (info, torrent)),
+                    Err(e) => {
+                        log::warn!("cannot fetch torrent of {}: {:?}", ep.title, e);
+                        None
+                    }
+                };
+                if let Some((info, torrent)) = from_files {
+                    log::info!("parsed {} from its torrent: {:?}", ep.title, info);
+                    fetched = Some(torrent);
+                    info
+                } else {
+                    report
+                        .skipped
+                        .push((ep.title.clone(), SkipReason::Unparseable));
+                    if !quarantine.iter().any(|q| q.hash == ep.torrent_hash) {
+                        let item = db.insert_quarantine_item(database::QuarantineItem {
+                            id: 0,
+                            bangumi_id: b.id,
+                            hash: ep.torrent_hash.clone(),
+                            title: ep.title.clone(),
+                            link: ep.link.clone(),
+                            torrent_url: ep.torrent_url.clone(),
+                        })?;
+                        report.quarantined.push(item);
+                    }
+                    continue;
+                }
+            }
+        };
+        // episodes imported from an existing library have no torrent hash
+        if b.episodes.iter().any(|e| {
+            e.hash.is_empty() && e.season == ep_info.season && e.episode == ep_info.episode
+        }) {
+            report
+                .skipped
+                .push((ep.title.clone(), SkipReason::Imported));
+            continue;
+        }
+        if let database::SubscribeMode::FromEpisode(from) = b.mode {
+            if ep_info.episode < from {
+                report
+                    .skipped
+                    .push((ep.title.clone(), SkipReason::BeforeFrom(from)));
+                continue;
+            }
+        }
+        log::info!("starting download: {:?}", ep);
+        match download_fetched(b, ep, fetched, &ep_info, &rules, db, cfg).await {
+            Ok(queued) => report.queued.push(queued),
+            Err(crate::error::Error::Update(UpdateError::Rejected { issue })) => {
+                log::warn!("torrent rejected: {} {:?}", ep.title, issue);
+                report.rejected.push((ep.title.clone(), issue.clone()));
+                report
+                    .skipped
+                    .push((ep.title.clone(), SkipReason::Rejected(issue)));
+            }
+            Err(e) => {
+                let task = database::RetryTask {
+                    hash: ep.torrent_hash.clone(),
+                    bangumi_id: b.id,
+                    title: ep.title.clone(),
+                    link: ep.link.clone(),
+                    torrent_url: ep.torrent_url.clone(),
+                    attempts: 0,
+                    next_try: 0,
+                    last_error: String::new(),
+                    gave_up: false,
+                };
+                report.failed.push(record_failure(db, cfg, task, &e)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Download the episodes numbered `from..=to` that are still in the feed,
+/// one release per episode, ignoring the subscribe mode, unless a check is
+/// running. Failed downloads go to the retry queue, rejected and given up
+/// releases are reported to the chats notified about the bangumi.
+pub async fn backfill(
+    b: database::Bangumi,
+    from: i16,
+    to: i16,
+    db: Arc<database::Client>,
+    cfg: Arc<bot::Config>,
+    tg: Arc<teloxide::Bot>,
+) -> Result<Vec<Queued>> {
+    let _guard = CHECK_LOCK
+        .try_lock()
+        .map_err(|_| UpdateError::AlreadyRunning)?;
+    // episodes a check just recorded
+    let b = db.get_bangumi(b.id)?.unwrap_or(b);
+    let rss = mikan::MikanRss::from_url(&b.rss_url)
+        .set_proxy(cfg.proxy.clone())?
+        .fetch()
+        .await?;
+    let rules = db.get_parse_rules()?;
+    let mut keys: Vec<(i8, i16)> = Vec::new();
+    let mut queued = Vec::new();
+    let mut rejected = Vec::new();
+    let mut failed = Vec::new();
+    for ep in rss.items.iter() {
+        if filter_reason(&b, ep).is_some() {
+            continue;
+        }
+        let ep_info = match parse_episode(&b, &rules, &ep.title) {
+            Ok(info) => info,
+            Err(e) => {
+                log::warn!("backfill skipped {}: {:?}", ep.title, e);
+                continue;
+            }
+        };
+        let key = (ep_info.season, ep_info.episode);
+        if ep_info.episode < from
+            || ep_info.episode > to
+            || keys.contains(&key)
+            || has_episode(&b, key.0, key.1)
+        {
+            continue;
+        }
+        log::info!("backfill download: {:?}", ep);
+        match download_episode(&b, ep, &ep_info, &db, &cfg).await {
+            Ok(q) => queued.push(q),
+            Err(crate::error::Error::Update(UpdateError::Rejected { issue })) => {
+                log::warn!("backfill rejected {}: {:?}", ep.title, issue);
+                rejected.push((ep.title.clone(), issue));
+                continue;
+            }
+            // retried later, no other release of the episode is needed
+            Err(e) => {
+                let task = database::RetryTask {
+                    hash: ep.torrent_hash.clone(),
+                    bangumi_id: b.id,
+                    title: ep.title.clone(),
+                    link: ep.link.clone(),
+                    torrent_url: ep.torrent_url.clone(),
+                    attempts: 0,
+                    next_try: 0,
+                    last_error: String::new(),
+                    gave_up: false,
+                };
+                failed.push(record_failure(&db, &cfg, task, &e)?);
+            }
+        }
+        keys.push(key);
+    }
+    if let Err(e) = notify_gave_up(&tg, &db, &cfg, &b, &failed).await {
+        log::error!("backfill notify error: {} {:?}", b.title, e);
+    }
+    if let Err(e) = notify_rejected(&tg, &db, &cfg, &b, &rejected).await {
+        log::error!("backfill notify error: {} {:?}", b.title, e);
+    }
+    Ok(queued)
+}
+
+enum Source<'a> {
+    TorrentFile(&'a str),
+    Magnet(&'a str),
+}
+
+/// Download the torrent of an RSS item into the temporary directory.
+async fn fetch_torrent(ep: &mikan::RssEpisode, cfg: &bot::Config) -> Result<(String, Torrent)> {
+    let torrent_url = ep.torrent_url.as_str();
+    let torrent_name = torrent_url
+        .split('/')
+        .next_back()
+        .filter(|n| !n.is_empty())
+        .ok_or(downloader::DownloaderError::InvalidTorrentUrl {
+            url: torrent_url.to_string(),
+        })?;
+    let torrent_path = format!("{}/{}", cfg.tmp_dir, torrent_name);
+    utils::ensure_dir(&cfg.tmp_dir).await?;
+    utils::download_file(torrent_url, &torrent_path, cfg.proxy.clone()).await?;
+    let torrent = Torrent::read_from_file(&torrent_path)?;
+    Ok((torrent_path, torrent))
+}
+
+/// Fetch the torrent of an RSS item, hand it to the downloader and record the
+/// episode, returning the library name it is saved under.
+pub async fn download_episode(
+    b: &database::Bangumi,
+    ep: &mikan::RssEpisode,
+    ep_info: &title_parser::ParseResult,
+    db: &database::Client,
+    cfg: &bot::Config,
+) -> Result<Queued> {
+    let rules = db.get_parse_rules()?;
+    download_fetched(b, ep, None, ep_info, &rules, db, cfg).await
+}
+
+/// Same as [`download_episode`] with the torrent possibly fetched already,
+/// noting when the video in it is named as another episode.
+async fn download_fetched(
+    b: &database::Bangumi,
+    ep: &mikan::RssEpisode,
+    fetched: Option<(String, Torrent)>,
+    ep_info: &title_parser::ParseResult,
+    rules: &[database::ParseRule],
+    db: &database::Client,
+    cfg: &bot::Config,
+) -> Result<Queued> {
+    let (torrent_path, torrent) = match fetched {
+        Some(fetched) => fetched,
+        None => fetch_torrent(ep, cfg).await?,
+    };
+    let (rejects, issues): (Vec<_>, Vec<_>) = inspect_torrent(
+        &torrent,
+        &cfg.torrent_size,
+        title_parser::episode_count(&ep.title),
+    )
+    .into_iter()
+    .partition(|issue| issue.rejects());
+    if let Some(issue) = rejects.into_iter().next() {
+        return Err(UpdateError::Rejected { issue }.into());
+    }
+    let mut queued = hand_off(
+        b,
+        Source::TorrentFile(&torrent_path),
+        &torrent.info_hash(),
+        &ep.title,
+        ep_info,
+        db,
+        cfg,
+    )
+    .await?;
+    queued.link = ep.link.clone();
+    queued.size = u64::try_from(torrent.length).ok();
+    queued.issues = issues;
+    queued.file_mismatch = file_mismatch(b, rules, &torrent, ep_info);
+    if let Some((season, episode)) = queued.file_mismatch {
+        log::warn!(
+            "{} is S{:02}E{:02} but its torrent has S{:02}E{:02}",
+            ep.title,
+            ep_info.season,
+            ep_info.episode,
+            season,
+            episode
+        );
+    }
+    Ok(queued)
+}
+
+/// Same as [`download_episode`] for a release only known by its magnet link.
+pub async fn download_magnet(
+    b: &database::Bangumi,
+    magnet: &str,
+    title: &str,
+    ep_info: &title_parser::ParseResult,
+    db: &database::Client,
+    cfg: &bot::Config,
+) -> Result<Queued> {
+    let hash = downloader::QbitDownloader::magnet_to_hash(magnet);
+    hand_off(b, Source::Magnet(magnet), &hash, title, ep_info, db, cfg).await
+}
+
+async fn hand_off(
+    b: &database::Bangumi,
+    source: Source<'_>,
+    torrent_hash: &str,
+    title: &str,
+    ep_info: &title_parser::ParseResult,
+    db: &database::Client,
+    cfg: &bot::Config,
+) -> Result<Queued> {
+    let save_dir = format!("{}/{}/Season {}", cfg.lib_dir, b.title, ep_info.season);
+    let save_name = format!("{} S{:02}E{:02}", b.title, ep_info.season, ep_info.episode);
+    let downloader = downloader::QbitDownloader::new().await?;
+    if cfg.organize_mode == organizer::OrganizeMode::Rename {
+        match source {
+            Source::TorrentFile(path) => {
+                downloader
+                    .download_by_torrent_to(path, torrent_hash, &save_dir, &save_name)
+                    .await?
+            }
+            Source::Magnet(url) => downloader.download_to(url, &save_dir, &save_name).await?,
+        }
+    } else {
+        match source {
+            Source::TorrentFile(path) => {
+                downloader
+                    .download_by_torrent_into(path, torrent_hash, &cfg.download_dir)
+                    .await?
+            }
+            Source::Magnet(url) => downloader.download_into(url, &cfg.download_dir).await?,
+        }
+        db.insert_organize_task(database::OrganizeTask {
+            hash: torrent_hash.to_string(),
+            bangumi_id: b.id,
+            save_dir,
+            save_name: save_name.clone(),
+            done: false,
+            files: vec![],
+        })?;
+    }
+    let policy = b.seeding.or(&cfg.seeding);
+    if let Err(e) = seeding::apply(&downloader, &[torrent_hash.to_string()], &policy).await {
+        log::warn!("cannot set seeding limits of {}: {:?}", torrent_hash, e);
+    }
+    db.add_episode(
+        b.id,
+        database::Episode {
+            season: ep_info.season,
+            episode: ep_info.episode,
+            hash: torrent_hash.to_string(),
+            title: title.to_string(),
+        },
+    )?;
+    Ok(Queued {
+        save_name,
+        title: title.to_string(),
+        link: String::new(),
+        season: ep_info.season,
+        episode: ep_info.episode,
+        fansub: ep_info.fansub.clone(),
+        resolution: title_parser::parse_resolution(title),
+        size: None,
+        file_mismatch: None,
+        issues: vec![],
+    })
+}
+
+/// Tell the chats notified about a bangumi which downloads were given up.
+async fn notify_gave_up(
+    tg: &teloxide::Bot,
+    db: &database::Client,
+    cfg: &bot::Config,
+    b: &database::Bangumi,
+    failed: &[database::RetryTask],
+) -> Result<()> {
+    let gave_up = failed.iter().filter(|t| t.gave_up).collect::<Vec<_>>();
+    if gave_up.is_empty() {
+        return Ok(());
+    }
+    for id in db.notify_chats(b)? {
+        let locale = db.chat_locale(id)?.unwrap_or(cfg.locale);
+        for task in gave_up.iter() {
+            let text = fill(
+                locale.text(Msg::GaveUpNotice),
+                &[
+                    &b.title,
+                    &task.title,
+                    &task.attempts,
+                    &task.last_error,
+                    &b.id,
+                ],
+            );
+            if let Err(e) = tg.send_message(ChatId(id), text).await {
+                log::error!("notify {} error: {:?}", id, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Tell the chats notified about a bangumi which torrents were not
+/// downloaded and why.
+async fn notify_rejected(
+    tg: &teloxide::Bot,
+    db: &database::Client,
+    cfg: &bot::Config,
+    b: &database::Bangumi,
+    rejected: &[(String, TorrentIssue)],
+) -> Result<()> {
+    if rejected.is_empty() {
+        return Ok(());
+    }
+    for id in db.notify_chats(b)? {
+        let locale = db.chat_locale(id)?.unwrap_or(cfg.locale);
+        for (title, issue) in rejected.iter() {
+            let text = fill(
+                locale.text(Msg::RejectedNotice),
+                &[&b.title, title, &issue.message(locale)],
+            );
+            if let Err(e) = tg.send_message(ChatId(id), text).await {
+                log::error!("notify {} error: {:?}", id, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Ask the chats notified about a bangumi for the episodes of titles that
+/// could not be parsed, offering the numbers in the title as answers.
+async fn notify_quarantined(
+    tg: &teloxide::Bot,
+    db: &database::Client,
+    cfg: &bot::Config,
+    b: &database::Bangumi,
+    items: &[database::QuarantineItem],
+) -> Result<()> {
+    if items.is_empty() {
+        return Ok(());
+    }
+    let season = b.episodes.iter().map(|e| e.season).max().unwrap_or(1);
+    for id in db.notify_chats(b)? {
+        let locale = db.chat_locale(id)?.unwrap_or(cfg.locale);
+        for item in items.iter() {
+            let text = fill(
+                locale.text(Msg::QuarantineNotice),
+                &[&b.title, &item.title, &item.id],
+            );
+            let mut rows = title_parser::episode_candidates(&item.title)
+                .into_iter()
+                .take(4)
+                .map(|episode| {
+                    let text = format!("S{:02}E{:02}", season, episode);
+                    vec![bot::Callback::Resolve(b.id, item.id, season, episode).button(&text)]
+                })
+                .collect::<Vec<_>>();
+            rows.push(vec![
+                bot::Callback::IgnoreItem(b.id, item.id).button(locale.text(Msg::ButtonIgnoreItem))
+            ]);
+            let sent = tg
+                .send_message(ChatId(id), text)
+                .reply_markup(InlineKeyboardMarkup::new(rows))
+                .await;
+            if let Err(e) = sent {
+                log::error!("notify {} error: {:?}", id, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Warn that the video in the torrent of a queued episode is named as
+/// another episode.
+pub fn mismatch_text(locale: Locale, q: &Queued, season: i8, episode: i16) -> String {
+    fill(
+        locale.text(Msg::FileMismatch),
+        &[
+            &format!("S{:02}E{:02}", q.season, q.episode),
+            &format!("S{:02}E{:02}", season, episode),
+        ],
+    )
+}
+
+pub fn issue_text(locale: Locale, q: &Queued, issue: &TorrentIssue) -> String {
+    fill(
+        locale.text(Msg::TorrentWarning),
+        &[
+            &format!("S{:02}E{:02}", q.season, q.episode),
+            &issue.message(locale),
+        ],
+    )
+}
+
+/// Send one photo message with the poster for all episodes queued for a
+/// bangumi in the same update, falling back to text without a poster.
+pub async fn notify_episodes(
+    tg: &teloxide::Bot,
+    db: &database::Client,
+    cfg: &bot::Config,
+    b: &database::Bangumi,
+    queued: &[Queued],
+) -> Result<()> {
+    let episodes = db
+        .get_bangumi(b.id)?
+        .map(|b| b.episodes)
+        .unwrap_or_default();
+    let mut lines = vec![b.title.clone()];
+    for q in queued.iter() {
+        let mut parts = vec![format!("S{:02}E{:02}", q.season, q.episode)];
+        if !q.fansub.is_empty() {
+            parts.push(q.fansub.clone());
+        }
+        parts.extend(q.resolution.clone());
+        parts.extend(q.size.map(utils::human_size));
+        lines.push(parts.join(" · "));
+    }
+    let mut seasons = queued.iter().map(|q| q.season).collect::<Vec<i8>>();
+    seasons.sort_unstable();
+    seasons.dedup();
+    let progress = seasons
+        .into_iter()
+        .map(|season| {
+            let mut numbers = episodes
+                .iter()
+                .filter(|e| e.season == season)
+                .map(|e| e.episode)
+                .collect::<Vec<i16>>();
+            numbers.sort();
+            numbers.dedup();
+            let latest = format!("{:02}", numbers.last().copied().unwrap_or_default());
+            (season, numbers.len(), latest)
+        })
+        .collect::<Vec<(i8, usize, String)>>();
+    let caption = |locale: Locale| {
+        let mut lines = lines.clone();
+        for q in queued.iter() {
+            if let Some((season, episode)) = q.file_mismatch {
+                lines.push(mismatch_text(locale, q, season, episode));
+            }
+            for issue in q.issues.iter() {
+                lines.push(issue_text(locale, q, issue));
+            }
+        }
+        for (season, count, latest) in progress.iter() {
+            lines.push(fill(
+                locale.text(Msg::SeasonProgress),
+                &[season, count, latest],
+            ));
+        }
+        let caption = lines.join("\n");
+        if caption.chars().count() > CAPTION_LIMIT {
+            caption.chars().take(CAPTION_LIMIT - 1).collect::<String>() + "…"
+        } else {
+            caption
+        }
+    };
+
+    let mikan_url = queued
+        .iter()
+        .map(|q| q.link.as_str())
+        .find(|l| !l.is_empty())
+        .map(|l| l.to_string())
+        .unwrap_or(format!("{}/{}", MIKAN_BANGUMI_URL, b.id));
+    let mikan_url = reqwest::Url::parse(&mikan_url).ok();
+    let mut groups = queued
+        .iter()
+        .map(|q| q.fansub.clone())
+        .filter(|f| !f.is_empty())
+        .collect::<Vec<String>>();
+    groups.sort();
+    groups.dedup();
+    // channel posts only get the link, there is nobody to press the actions
+    let keyboard = |locale: Locale, actions: bool| {
+        let mut rows = Vec::new();
+        if let Some(url) = mikan_url.clone() {
+            rows.push(vec![InlineKeyboardButton::url(
+                locale.text(Msg::ButtonOpenMikan),
+                url,
+            )]);
+        }
+        if actions {
+            for group in groups.iter() {
+                let text = fill(locale.text(Msg::ButtonSkipGroup), &[group]);
+                rows.push(vec![
+                    bot::Callback::SkipGroup(b.id, group.clone()).button(&text)
+                ]);
+            }
+            rows.push(vec![
+                bot::Callback::Pause(b.id).button(locale.text(Msg::ButtonPause))
+            ]);
+        }
+        InlineKeyboardMarkup::new(rows)
+    };
+
+    let poster = match utils::cached_file(
+        &b.poster_url,
+        &format!("{}/posters", cfg.tmp_dir),
+        cfg.proxy.clone(),
+    )
+    .await
+    {
+        Ok(path) => Some(path),
+        Err(e) => {
+            log::warn!("poster error: {:?}", e);
+            None
+        }
+    };
+    let mut targets: Vec<(Recipient, Locale, bool)> = Vec::new();
+    for id in db.notify_chats(b)? {
+        let locale = db.chat_locale(id)?.unwrap_or(cfg.locale);
+        targets.push((Recipient::Id(ChatId(id)), locale, true));
+    }
+    if let Some(channel) = b.channel.as_ref() {
+        let recipient = match channel.parse::<i64>() {
+            Ok(id) => Recipient::Id(ChatId(id)),
+            Err(_) => Recipient::ChannelUsername(channel.clone()),
+        };
+        targets.push((recipient, cfg.locale, false));
+    }
+    // one chat or channel the bot cannot post to must not keep the others
+    // from being notified
+    for (recipient, locale, actions) in targets {
+        let sent = match poster.as_ref() {
+            Some(path) => tg
+                .send_photo(recipient.clone(), InputFile::file(path))
+                .caption(caption(locale))
+                .reply_markup(keyboard(locale, actions))
+                .await
+                .map(|_| ()),
+            None => tg
+                .send_message(recipient.clone(), caption(locale))
+                .reply_markup(keyboard(locale, actions))
+                .await
+                .map(|_| ()),
+        };
+        if let Err(e) = sent {
+            log::error!("notify {:?} error: {:?}", recipient, e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), 300);
+        assert_eq!(retry_delay(2), 600);
+        assert_eq!(retry_delay(4), 2400);
+        assert_eq!(retry_delay(10), 12 * 3600);
+        assert_eq!(retry_delay(u32::MAX), 12 * 3600);
+    }
+
+    fn file(length: i64, path: &str) -> lava_torrent::torrent::v1::File {
+        lava_torrent::torrent::v1::File {
+            length,
+            path: path.into(),
+            extra_fields: None,
+        }
+    }
+
+    fn torrent(files: Vec<lava_torrent::torrent::v1::File>) -> Torrent {
+        Torrent {
+            announce: None,
+            announce_list: None,
+            length: files.iter().map(|f| f.length).sum(),
+            files: Some(files),
+            name: "[LoliHouse] Frieren".to_string(),
+            piece_length: 16384,
+            pieces: vec![],
+            extra_fields: None,
+            extra_info_fields: None,
+        }
+    }
+
+    #[test]
+    fn test_video_files() {
+        let mut torrent = torrent(vec![]);
+        torrent.files = None;
+        torrent.name = "[LoliHouse] Frieren - 06 [1080p].mkv".to_string();
+        assert_eq!(video_files(&torrent), vec![torrent.name.clone()]);
+        torrent.files = Some(vec![
+            file(10, "SPs/[LoliHouse] Frieren - NCOP [1080p].mkv"),
+            file(90, "[LoliHouse] Frieren - 06 [1080p].mkv"),
+            file(1, "[LoliHouse] Frieren - 06 [1080p].ass"),
+        ]);
+        assert_eq!(
+            video_files(&torrent),
+            vec![
+                "[LoliHouse] Frieren - 06 [1080p].mkv",
+                "[LoliHouse] Frieren - NCOP [1080p].mkv"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inspect_torrent() {
+        let size = 20..=1000;
+        let good = torrent(vec![
+            file(10, "NCOP.mkv"),
+            file(300, "Frieren - 06.mkv"),
+            file(1, "Frieren - 06.ass"),
+        ]);
+        assert!(inspect_torrent(&good, &size, 1).is_empty());
+        let small = torrent(vec![file(10, "Frieren - 06.mp4")]);
+        assert_eq!(
+            inspect_torrent(&small, &size, 1),
+            vec![TorrentIssue::Size {
+                size: 10,
+                min: 20,
+                max: 1000
+            }]
+        );
+        let fake = torrent(vec![file(300, "Frieren - 06.mp4"), file(1, "codec.EXE")]);
+        let issues = inspect_torrent(&fake, &size, 1);
+        assert_eq!(
+            issues,
+            vec![TorrentIssue::ForbiddenFile("codec.EXE".to_string())]
+        );
+        assert!(issues[0].rejects());
+        let batch = torrent(vec![
+            file(300, "Frieren - 06.mkv"),
+            file(290, "Frieren - 07.mkv"),
+        ]);
+        let issues = inspect_torrent(&batch, &size, 1);
+        assert_eq!(
+            issues,
+            vec![TorrentIssue::VideoCount {
+                videos: 2,
+                episodes: 1
+            }]
+        );
+        assert!(!issues[0].rejects());
+        assert!(inspect_torrent(&batch, &size, 2).is_empty());
+        let empty = torrent(vec![file(300, "Frieren - 06.zip")]);
+        assert_eq!(
+            inspect_torrent(&empty, &size, 1),
+            vec![TorrentIssue::VideoCount {
+                videos: 0,
+                episodes: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn test_file_mismatch() {
+        let b: database::Bangumi = serde_json::from_value(serde_json::json!({
+            "id": 1, "title": "Frieren", "weekday": 5, "poster_url": "",
+            "rss_url": "", "enabled": true, "not_contains": [], "downloaded": [],
+        }))
+        .unwrap();
+        let ep_info =
+            title_parser::parse("[LoliHouse] Sousou no Frieren S02 - 06 [1080p]").unwrap();
+        assert_eq!((ep_info.season, ep_info.episode), (2, 6));
+        let mismatch = |names: &[&str]| {
+            let files = names.iter().map(|name| file(300, name)).collect();
+            file_mismatch(&b, &[], &torrent(files), &ep_info)
+        };
+        // no season in the file name
+        assert_eq!(
+            mismatch(&["[LoliHouse] Sousou no Frieren - 06 [1080p].mkv"]),
+            None
+        );
+        assert_eq!(
+            mismatch(&["[LoliHouse] Sousou no Frieren - 07 [1080p].mkv"]),
+            Some((2, 7))
+        );
+        assert_eq!(
+            mismatch(&["[LoliHouse] Sousou no Frieren S01 - 06 [1080p].mkv"]),
+            Some((1, 6))
+        );
+        assert_eq!(
+            mismatch(&[
+                "[LoliHouse] Sousou no Frieren - 05 [1080p].mkv",
+                "[LoliHouse] Sousou no Frieren - 06 [1080p].mkv",
+            ]),
+            None
+        );
+    }
+}
@@ -1,4 +1,5 @@
-use anyhow::Result;
+use crate::error::Result;
+use crate::rate_limit;
 use reqwest::{self, Proxy};
 use std::{io::Write, path::Path};
 
@@ -7,16 +8,33 @@ pub async fn download_file(url: &str, save_path: &str, proxy: Option<Proxy>) ->
     if let Some(proxy) = proxy {
         client = reqwest::Client::builder().proxy(proxy).build()?;
     }
-    let mut file = std::fs::File::create(save_path)?;
+    rate_limit::acquire(url).await;
     let mut resp = client.get(url).send().await?;
     let mut content = Vec::new();
     while let Some(chunk) = resp.chunk().await? {
         content.extend_from_slice(&chunk);
     }
+    // only now, a failed download must not leave a file `cached_file` takes
+    let mut file = std::fs::File::create(save_path)?;
     file.write_all(&content)?;
     Ok(())
 }
 
+/// Download `url` into `dir` unless a file with the same name is already there.
+pub async fn cached_file(url: &str, dir: &str, proxy: Option<Proxy>) -> Result<String> {
+    ensure_dir(dir).await?;
+    let name = url
+        .split('?')
+        .next()
+        .and_then(|u| u.split('/').next_back())
+        .unwrap_or_default();
+    let path = format!("{}/{}", dir, name);
+    if !Path::new(&path).exists() {
+        download_file(url, &path, proxy).await?;
+    }
+    Ok(path)
+}
+
 pub async fn ensure_dir(path: &str) -> Result<()> {
     if !std::path::Path::new(path).exists() {
         std::fs::create_dir_all(path).unwrap();
@@ -38,3 +56,44 @@ pub fn file_stem(file_name: &str) -> Option<&str> {
 pub fn file_extension(file_name: &str) -> Option<&str> {
     Path::new(file_name).extension()?.to_str()
 }
+
+pub fn walk_files(dir: &Path) -> std::io::Result<Vec<std::path::PathBuf>> {
+    let mut files = Vec::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(walk_files(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub fn is_video(file_name: &str) -> bool {
+    matches!(
+        file_extension(file_name)
+            .map(|e| e.to_lowercase())
+            .as_deref(),
+        Some("mkv" | "mp4" | "avi" | "m4v" | "mov" | "ts" | "webm" | "flv" | "rmvb" | "wmv")
+    )
+}
+
+pub fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}